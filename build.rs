@@ -1,18 +1,50 @@
+use std::fmt::Write as _;
+use std::path::Path;
+
 use anyhow::*;
-use fs_extra::copy_items;
-use fs_extra::dir::CopyOptions;
-use std::env;
 
 fn main() -> Result<()> {
-    // This tells cargo to rerun this script if something in /res/ changes.
-    // println!("cargo:rerun-if-changed=res/*");
+    // Assets are found at runtime, see `engine::resource::default_asset_roots`,
+    // so nothing needs copying into the build directory.
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=res");
+
+    embed_assets()
+}
+
+// Writes the table behind `engine::resource::embedded`, with an
+// `include_bytes!` of every file in res/ when the `embed-assets` feature is
+// on and empty otherwise.
+fn embed_assets() -> Result<()> {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR")?;
+    let res = Path::new(&manifest_dir).join("res");
+
+    let mut assets = Vec::new();
+    if std::env::var_os("CARGO_FEATURE_EMBED_ASSETS").is_some() {
+        let pattern = res.join("**").join("*");
+        for path in glob::glob(pattern.to_str().context("res/ path isn't UTF-8")?)? {
+            let path = path?;
+            if !path.is_file() {
+                continue;
+            }
+            let name = path.strip_prefix(&res)?
+                .components()
+                .map(|component| component.as_os_str().to_str().context("asset name isn't UTF-8"))
+                .collect::<Result<Vec<_>>>()?
+                .join("/");
+            assets.push((name, path));
+        }
+    }
+    // Sorted so lookups can binary search.
+    assets.sort();
 
-    // let out_dir = env::var("OUT_DIR")?;
-    // let mut copy_options = CopyOptions::new();
-    // copy_options.overwrite = true;
-    // let mut paths_to_copy = Vec::new();
-    // paths_to_copy.push("res/");
-    // copy_items(&paths_to_copy, out_dir, &copy_options)?;
+    let mut table = String::from("&[\n");
+    for (name, path) in &assets {
+        writeln!(table, "    ({:?}, include_bytes!({:?})),", name, path)?;
+    }
+    table.push_str("]\n");
 
+    let out = Path::new(&std::env::var("OUT_DIR")?).join("embedded_assets.rs");
+    std::fs::write(out, table)?;
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,6 @@
+// The camera, bound by every pass that draws the scene.
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
@@ -0,0 +1,193 @@
+// Post-processing passes, each drawn as a single fullscreen triangle
+#include "include/color.wgsl"
+#include "include/fullscreen.wgsl"
+
+struct PostSettings {
+    exposure: f32,
+    bloom_threshold: f32,
+    bloom_intensity: f32,
+    vignette_intensity: f32,
+    tonemapper: u32,
+    flags: u32,
+    lut_size: f32,
+    _padding: f32,
+};
+
+let FLAG_BLOOM: u32 = 1u;
+let FLAG_COLOR_GRADING: u32 = 2u;
+let FLAG_VIGNETTE: u32 = 4u;
+
+let TONEMAP_NONE: u32 = 0u;
+let TONEMAP_REINHARD: u32 = 1u;
+let TONEMAP_ACES: u32 = 2u;
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> settings: PostSettings;
+
+fn uv_offset(uv: vec2<f32>, texel: vec2<f32>, x: f32, y: f32) -> vec2<f32> {
+    return uv + texel * vec2<f32>(x, y);
+}
+
+// Bloom
+
+fn downsample13(uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_source));
+    let a = textureSample(t_source, s_source, uv + texel * vec2<f32>(-2.0, -2.0)).rgb;
+    let b = textureSample(t_source, s_source, uv + texel * vec2<f32>(0.0, -2.0)).rgb;
+    let c = textureSample(t_source, s_source, uv + texel * vec2<f32>(2.0, -2.0)).rgb;
+    let d = textureSample(t_source, s_source, uv + texel * vec2<f32>(-2.0, 0.0)).rgb;
+    let e = textureSample(t_source, s_source, uv).rgb;
+    let f = textureSample(t_source, s_source, uv + texel * vec2<f32>(2.0, 0.0)).rgb;
+    let g = textureSample(t_source, s_source, uv + texel * vec2<f32>(-2.0, 2.0)).rgb;
+    let h = textureSample(t_source, s_source, uv + texel * vec2<f32>(0.0, 2.0)).rgb;
+    let i = textureSample(t_source, s_source, uv + texel * vec2<f32>(2.0, 2.0)).rgb;
+    let j = textureSample(t_source, s_source, uv + texel * vec2<f32>(-1.0, -1.0)).rgb;
+    let k = textureSample(t_source, s_source, uv + texel * vec2<f32>(1.0, -1.0)).rgb;
+    let l = textureSample(t_source, s_source, uv + texel * vec2<f32>(-1.0, 1.0)).rgb;
+    let m = textureSample(t_source, s_source, uv + texel * vec2<f32>(1.0, 1.0)).rgb;
+
+    var color = e * 0.125;
+    color = color + (a + c + g + i) * 0.03125;
+    color = color + (b + d + f + h) * 0.0625;
+    color = color + (j + k + l + m) * 0.125;
+    return color;
+}
+
+@fragment
+fn fs_bloom_prefilter(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let color = downsample13(in.uv);
+    let brightness = luminance(color);
+    let contribution = max(brightness - settings.bloom_threshold, 0.0) / max(brightness, 0.0001);
+    return vec4<f32>(color * contribution, 1.0);
+}
+
+@fragment
+fn fs_bloom_downsample(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(downsample13(in.uv), 1.0);
+}
+
+// Blended additively onto the next larger mip by the pipeline.
+@fragment
+fn fs_bloom_upsample(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_source));
+    var color = textureSample(t_source, s_source, uv_offset(in.uv, texel, -1.0, -1.0)).rgb;
+    color = color + textureSample(t_source, s_source, uv_offset(in.uv, texel, 0.0, -1.0)).rgb * 2.0;
+    color = color + textureSample(t_source, s_source, uv_offset(in.uv, texel, 1.0, -1.0)).rgb;
+    color = color + textureSample(t_source, s_source, uv_offset(in.uv, texel, -1.0, 0.0)).rgb * 2.0;
+    color = color + textureSample(t_source, s_source, in.uv).rgb * 4.0;
+    color = color + textureSample(t_source, s_source, uv_offset(in.uv, texel, 1.0, 0.0)).rgb * 2.0;
+    color = color + textureSample(t_source, s_source, uv_offset(in.uv, texel, -1.0, 1.0)).rgb;
+    color = color + textureSample(t_source, s_source, uv_offset(in.uv, texel, 0.0, 1.0)).rgb * 2.0;
+    color = color + textureSample(t_source, s_source, uv_offset(in.uv, texel, 1.0, 1.0)).rgb;
+    return vec4<f32>(color / 16.0, 1.0);
+}
+
+// Composite: exposure, bloom, tonemapping, color grading and vignette
+
+@group(0) @binding(3)
+var t_bloom: texture_2d<f32>;
+@group(0) @binding(4)
+var t_lut: texture_3d<f32>;
+
+fn tonemap_reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (vec3<f32>(1.0) + color);
+}
+
+// Narkowicz's fit of the ACES filmic curve.
+fn tonemap_aces(color: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((color * (a * color + b)) / (color * (c * color + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+@fragment
+fn fs_composite(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    var color = textureSample(t_source, s_source, in.uv).rgb;
+    let bloom = textureSample(t_bloom, s_source, in.uv).rgb;
+    if ((settings.flags & FLAG_BLOOM) != 0u) {
+        color = color + bloom * settings.bloom_intensity;
+    }
+
+    color = color * settings.exposure;
+
+    if (settings.tonemapper == TONEMAP_REINHARD) {
+        color = tonemap_reinhard(color);
+    } else if (settings.tonemapper == TONEMAP_ACES) {
+        color = tonemap_aces(color);
+    }
+    color = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+
+    // Sample at texel centres so the LUT's edges map exactly to 0 and 1.
+    let lut_scale = (settings.lut_size - 1.0) / settings.lut_size;
+    let lut_offset = 0.5 / settings.lut_size;
+    let graded = textureSample(t_lut, s_source, color * lut_scale + lut_offset).rgb;
+    if ((settings.flags & FLAG_COLOR_GRADING) != 0u) {
+        color = graded;
+    }
+
+    if ((settings.flags & FLAG_VIGNETTE) != 0u) {
+        let centered = in.uv - vec2<f32>(0.5);
+        let falloff = 1.0 - dot(centered, centered) * settings.vignette_intensity * 2.0;
+        color = color * clamp(falloff, 0.0, 1.0);
+    }
+
+    return vec4<f32>(color, 1.0);
+}
+
+// FXAA
+
+let FXAA_REDUCE_MIN: f32 = 0.0078125;
+let FXAA_REDUCE_MUL: f32 = 0.125;
+let FXAA_SPAN_MAX: f32 = 8.0;
+
+@fragment
+fn fs_fxaa(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_source));
+
+    let rgb_nw = textureSample(t_source, s_source, uv_offset(in.uv, texel, -1.0, -1.0)).rgb;
+    let rgb_ne = textureSample(t_source, s_source, uv_offset(in.uv, texel, 1.0, -1.0)).rgb;
+    let rgb_sw = textureSample(t_source, s_source, uv_offset(in.uv, texel, -1.0, 1.0)).rgb;
+    let rgb_se = textureSample(t_source, s_source, uv_offset(in.uv, texel, 1.0, 1.0)).rgb;
+    let rgb_m = textureSample(t_source, s_source, in.uv).rgb;
+
+    // Edge detection works best on perceptual luma, and the intermediate
+    // target stores linear values.
+    let luma_nw = sqrt(luminance(rgb_nw));
+    let luma_ne = sqrt(luminance(rgb_ne));
+    let luma_sw = sqrt(luminance(rgb_sw));
+    let luma_se = sqrt(luminance(rgb_se));
+    let luma_m = sqrt(luminance(rgb_m));
+
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    var dir = vec2<f32>(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    let dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * (0.25 * FXAA_REDUCE_MUL), FXAA_REDUCE_MIN);
+    let rcp_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * rcp_dir_min, vec2<f32>(-FXAA_SPAN_MAX), vec2<f32>(FXAA_SPAN_MAX)) * texel;
+
+    let rgb_a = 0.5 * (
+        textureSample(t_source, s_source, in.uv + dir * (1.0 / 3.0 - 0.5)).rgb +
+        textureSample(t_source, s_source, in.uv + dir * (2.0 / 3.0 - 0.5)).rgb
+    );
+    let rgb_b = rgb_a * 0.5 + 0.25 * (
+        textureSample(t_source, s_source, in.uv + dir * -0.5).rgb +
+        textureSample(t_source, s_source, in.uv + dir * 0.5).rgb
+    );
+
+    let luma_b = sqrt(luminance(rgb_b));
+    if (luma_b < luma_min || luma_b > luma_max) {
+        return vec4<f32>(rgb_a, 1.0);
+    }
+    return vec4<f32>(rgb_b, 1.0);
+}
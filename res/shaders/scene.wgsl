@@ -1,9 +1,6 @@
+#include "include/camera.wgsl"
+
 // Vertex shader
-struct CameraUniform {
-    view_proj: mat4x4<f32>,
-};
-@group(1) @binding(0)
-var<uniform> camera: CameraUniform;
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
@@ -34,6 +31,12 @@ var s_diffuse: sampler;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+#ifdef ALPHA_TEST
+    if (color.a < 0.5) {
+        discard;
+    }
+#endif
+    return color;
 }
  
\ No newline at end of file
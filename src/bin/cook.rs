@@ -0,0 +1,52 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use rmagic::engine::resource::cook::{cook_directory, CookOptions};
+
+const USAGE: &str = "usage: cook [<dir>] [-o <out dir>] [--no-compress] [--force]
+
+Cooks every file under <dir> (default res) into <out dir> (default cooked):
+models into binary meshes, images into mipmapped and block compressed
+textures, and shaders are validated. Unchanged inputs are skipped unless
+--force is given, and the outputs of deleted inputs are removed. Run the
+engine with `--asset-path cooked` to use the result.";
+
+fn main() -> anyhow::Result<()> {
+    let mut input = PathBuf::from("res");
+    let mut output = PathBuf::from("cooked");
+    let mut options = CookOptions::default();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--out" => output = args.next().map(PathBuf::from).context("-o expects a value")?,
+            "--no-compress" => options.compress_textures = false,
+            "--force" => options.force = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ if arg.starts_with('-') => anyhow::bail!("unknown option {}\n\n{}", arg, USAGE),
+            _ => input = PathBuf::from(arg),
+        }
+    }
+
+    let report = cook_directory(&input, &output, &options)?;
+    for name in &report.cooked {
+        println!("cooked {}", name);
+    }
+    for name in &report.removed {
+        println!("removed {}", name);
+    }
+    for (name, error) in &report.failed {
+        eprintln!("failed to cook {}: {:?}", name, error);
+    }
+    println!(
+        "{} cooked, {} unchanged, {} removed, {} failed",
+        report.cooked.len(), report.unchanged, report.removed.len(), report.failed.len(),
+    );
+    if !report.failed.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use rmagic::engine::resource::pack::{pack_directory, Compression, PackReader};
+
+const USAGE: &str = "usage: pack [<dir>] [-o <out.pak>] [--store]
+       pack --list <file.pak>
+
+Packs every file under <dir> (default res) into <out.pak> (default assets.pak),
+deflating files that get smaller. --store skips compression.";
+
+fn main() -> anyhow::Result<()> {
+    let mut dir = PathBuf::from("res");
+    let mut out = PathBuf::from("assets.pak");
+    let mut compression = Compression::Deflate;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--out" => out = args.next().map(PathBuf::from).context("-o expects a value")?,
+            "--store" => compression = Compression::None,
+            "--list" => {
+                let pack = PackReader::open(args.next().context("--list expects a value")?)?;
+                let mut paths = pack.paths().collect::<Vec<_>>();
+                paths.sort_unstable();
+                for path in paths {
+                    println!("{}", path);
+                }
+                return Ok(());
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ if arg.starts_with('-') => anyhow::bail!("unknown option {}\n\n{}", arg, USAGE),
+            _ => dir = PathBuf::from(arg),
+        }
+    }
+
+    let count = pack_directory(&dir, &out, compression)?;
+    println!("packed {} files from {} into {}", count, dir.display(), out.display());
+    Ok(())
+}
@@ -1,7 +1,9 @@
 
-use cgmath::{Matrix4, Vector3, Deg, perspective, vec3, InnerSpace, Point3, EuclideanSpace};
-use winit::window::Window;
+use cgmath::{Matrix4, Vector3, Deg, perspective, Point3};
 
+use crate::engine::renderkit::buffers::uniform::{Uniform, UniformField, WgslType};
+
+#[derive(Clone)]
 pub struct Camera {
     pub eye: Point3<f32>,
     pub target: Point3<f32>,
@@ -18,6 +20,18 @@ pub struct CameraUniform {
     view_proj: [[f32; 4]; 4],
 }
 
+impl Uniform for CameraUniform {
+    const FIELDS: &'static [UniformField] = &[
+        UniformField { name: "view_proj", offset: std::mem::offset_of!(CameraUniform, view_proj), ty: WgslType::Mat4x4 },
+    ];
+}
+
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CameraUniform {
     pub fn new() -> Self {
         use cgmath::SquareMatrix;
@@ -46,8 +60,8 @@ impl Camera {
         OPENGL_TO_WGPU_MATRIX * proj * view
     }
 
-    pub fn new(window: &Window, config: &wgpu::SurfaceConfiguration) -> Self {
-        let camera = Camera {
+    pub fn new(config: &wgpu::SurfaceConfiguration) -> Self {
+        Camera {
             eye: (0.0, 1.0, 2.0).into(),
             target: (0.0,0.0,0.0).into(),
             up: Vector3::unit_y(),
@@ -55,7 +69,10 @@ impl Camera {
             fov: 45.0,
             znear: 0.1,
             zfar: 100.0
-        };
-        camera
+        }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.aspect = width as f32 / height as f32;
     }
 }
\ No newline at end of file
@@ -57,20 +57,23 @@ impl CameraController {
         }
     }
 
-    pub fn update_camera(&self, camera: &mut Camera) {
+    // `speed` is in units per second, `dt` in seconds.
+    pub fn update_camera(&self, camera: &mut Camera, dt: f32) {
         use cgmath::InnerSpace;
 
+        let step = self.speed * dt;
+
         let forward = camera.target - camera.eye;
 
         let forward_norm = forward.normalize();
         let forward_mag = forward.magnitude();
 
-        if self.is_forward_pressed && forward_mag > self.speed {
-            camera.eye += forward_norm * self.speed;
+        if self.is_forward_pressed && forward_mag > step {
+            camera.eye += forward_norm * step;
         }
 
         if self.is_backward_pressed {
-            camera.eye -= forward_norm * self.speed;
+            camera.eye -= forward_norm * step;
         }
 
         let right = forward_norm.cross(camera.up);
@@ -80,11 +83,11 @@ impl CameraController {
         let forward_mag = forward.magnitude();
 
         if self.is_right_pressed {
-            camera.eye = camera.target - (forward + right * self.speed).normalize() * forward_mag;
+            camera.eye = camera.target - (forward + right * step).normalize() * forward_mag;
         }
 
         if self.is_left_pressed {
-            camera.eye = camera.target - (forward - right * self.speed).normalize() * forward_mag;
+            camera.eye = camera.target - (forward - right * step).normalize() * forward_mag;
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,235 @@
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use log::info;
+use winit::{
+    dpi::PhysicalSize,
+    event::*,
+    event_loop::{ControlFlow, EventLoop},
+    window::{Fullscreen, Window, WindowBuilder},
+};
+
+use super::{
+    config::{EngineConfig, FullscreenMode},
+    recorder::{Recorder, RecordingConfig},
+    renderkit::{adapter, capabilities::DeviceRequirements, RenderKit},
+    resource,
+    time::Time,
+};
+
+// A game built on the engine. Everything except `init` has a default so apps
+// only implement the hooks they need.
+pub trait App: Sized {
+    fn init(window: &Window, renderkit: &mut RenderKit) -> anyhow::Result<Self>;
+
+    // GPU features and limits the app needs, asked for before the device is
+    // created. Check `renderkit.gpu.capabilities` for what optional ones it got.
+    fn device_requirements() -> DeviceRequirements {
+        DeviceRequirements::default()
+    }
+
+    // Called once per rendered frame; use `time.alpha()` to interpolate.
+    fn update(&mut self, _renderkit: &mut RenderKit, _time: &Time) {}
+
+    // Called zero or more times per frame at the configured fixed timestep.
+    fn fixed_update(&mut self, _renderkit: &mut RenderKit, _time: &Time) {}
+
+    // Called right before the frame is drawn, e.g. to upload uniforms.
+    fn render(&mut self, _renderkit: &mut RenderKit) {}
+
+    // Returns true if the event was consumed and the engine shouldn't act on it.
+    fn input(&mut self, _event: &WindowEvent) -> bool {
+        false
+    }
+
+    fn resize(&mut self, _renderkit: &mut RenderKit, _size: PhysicalSize<u32>) {}
+
+    // Called after the GPU device was lost and recreated. Renderables held by
+    // the renderkit are restored already, anything else the app created on the
+    // old device has to be recreated here.
+    fn device_recovered(&mut self, _renderkit: &mut RenderKit) {}
+
+    fn shutdown(&mut self, _renderkit: &mut RenderKit) {}
+}
+
+pub struct Engine;
+
+impl Engine {
+    // Creates the window and renderer, then hands control to the event loop.
+    // Only returns if setup fails, or when run as `<binary> adapters`, which
+    // prints the available GPU adapters instead of starting the app.
+    pub fn run<A: App + 'static>(config: EngineConfig) -> anyhow::Result<()> {
+        if std::env::args().nth(1).as_deref() == Some("adapters") {
+            print!("{}", adapter::report(config.backends, &config.adapter, config.power_preference));
+            return Ok(());
+        }
+
+        // RUST_LOG still takes precedence over the configured level.
+        env_logger::Builder::new()
+            .filter_level(config.log_level)
+            .parse_env(env_logger::Env::default())
+            .init();
+
+        resource::set_asset_roots(&resource::default_asset_roots(&config.asset_paths));
+        info!("asset mounts: {:?}", resource::vfs::vfs().describe());
+
+        // In recording mode every redraw advances the simulation by exactly one
+        // frame and is captured, however long rendering takes. Set up before
+        // the window so bad recording flags fail early.
+        let mut recorder = RecordingConfig::from_args()?.map(Recorder::new).transpose()?;
+
+        let event_loop = EventLoop::new();
+        let window = WindowBuilder::new()
+            .with_title(&config.title)
+            .with_inner_size(PhysicalSize::new(config.width, config.height))
+            .with_fullscreen(Self::fullscreen(&event_loop, &config)?)
+            .build(&event_loop)?;
+
+        let mut renderkit = pollster::block_on(RenderKit::new(&window, &config, A::device_requirements()))?;
+        let mut app = A::init(&window, &mut renderkit)?;
+
+        let mut time = Time::new(config.fixed_timestep);
+        let frame_rate = config.frame_rate;
+        let mut next_draw = Instant::now();
+
+        event_loop.run(move |event, _, control_flow| match event {
+            Event::WindowEvent {
+                ref event,
+                window_id,
+            } if window_id == window.id() => {
+                if app.input(event) {
+                    return;
+                }
+                match event {
+                    WindowEvent::CloseRequested | WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                virtual_keycode: Some(VirtualKeyCode::Escape),
+                                ..
+                            },
+                        ..
+                    } => *control_flow = ControlFlow::Exit,
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F12),
+                                ..
+                            },
+                        ..
+                    } => {
+                        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+                        let path = format!("screenshot-{}.png", timestamp);
+                        match renderkit.screenshot(&path) {
+                            Ok(_) => info!("saved screenshot to {}", path),
+                            Err(e) => eprintln!("{:?}", e),
+                        }
+                    }
+                    WindowEvent::Resized(physical_size) => {
+                        Self::resize(&mut app, &mut renderkit, *physical_size);
+                    }
+                    WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                        Self::resize(&mut app, &mut renderkit, **new_inner_size);
+                    }
+                    _ => (),
+                }
+            }
+            Event::RedrawRequested(window_id) if window_id == window.id() => {
+                if renderkit.is_device_lost() {
+                    match renderkit.recover(&window) {
+                        Ok(_) => {
+                            info!("recovered from GPU device loss");
+                            app.device_recovered(&mut renderkit);
+                        }
+                        Err(e) => {
+                            eprintln!("{:?}", e);
+                            *control_flow = ControlFlow::Exit;
+                            return;
+                        }
+                    }
+                }
+
+                renderkit.update_assets();
+
+                let steps = match &recorder {
+                    Some(recorder) => time.advance(recorder.timestep()),
+                    None => time.tick(),
+                };
+                for _ in 0..steps {
+                    app.fixed_update(&mut renderkit, &time);
+                }
+                app.update(&mut renderkit, &time);
+                app.render(&mut renderkit);
+
+                if let Some(recorder) = &mut recorder {
+                    let result = renderkit.capture_frame().and_then(|frame| recorder.write_frame(&frame));
+                    if let Err(e) = result {
+                        eprintln!("{:?}", e);
+                        *control_flow = ControlFlow::Exit;
+                    } else if recorder.is_finished() {
+                        *control_flow = ControlFlow::Exit;
+                    }
+                    return;
+                }
+                match renderkit.render() {
+                    Ok(_) => {}
+                    // Reconfigure the surface if lost
+                    Err(wgpu::SurfaceError::Lost) => Self::resize(&mut app, &mut renderkit, window.inner_size()),
+                    // The system is out of memory, we should probably quit
+                    Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
+                    // All other errors (Outdated, Timeout) should be resolved by the next frame
+                    Err(e) => eprintln!("{:?}", e),
+                }
+            }
+            Event::MainEventsCleared => {
+                // Recording and uncapped mode draw as fast as possible,
+                // otherwise wait until the next frame is due.
+                let frame_duration = if recorder.is_some() { None } else { frame_rate.frame_duration() };
+                match frame_duration {
+                    None => {
+                        *control_flow = ControlFlow::Poll;
+                        window.request_redraw();
+                    }
+                    Some(frame_duration) => {
+                        let now = Instant::now();
+                        if now >= next_draw {
+                            // Don't try to catch up on frames we missed.
+                            next_draw = (next_draw + frame_duration).max(now);
+                            window.request_redraw();
+                        }
+                        *control_flow = ControlFlow::WaitUntil(next_draw);
+                    }
+                }
+            }
+            Event::LoopDestroyed => app.shutdown(&mut renderkit),
+            _ => (),
+        });
+    }
+
+    fn fullscreen(event_loop: &EventLoop<()>, config: &EngineConfig) -> anyhow::Result<Option<Fullscreen>> {
+        Ok(match config.fullscreen {
+            FullscreenMode::Windowed => None,
+            FullscreenMode::Borderless => Some(Fullscreen::Borderless(None)),
+            FullscreenMode::Exclusive => {
+                let monitor = event_loop.primary_monitor().context("exclusive fullscreen needs a primary monitor")?;
+                // Prefer the configured resolution, then the largest and fastest mode.
+                let mode = monitor
+                    .video_modes()
+                    .max_by_key(|mode| {
+                        let size = mode.size();
+                        let exact = size.width == config.width && size.height == config.height;
+                        (exact, size.width * size.height, mode.refresh_rate_millihertz())
+                    })
+                    .context("the primary monitor reports no video modes")?;
+                Some(Fullscreen::Exclusive(mode))
+            }
+        })
+    }
+
+    fn resize<A: App>(app: &mut A, renderkit: &mut RenderKit, size: PhysicalSize<u32>) {
+        if size.width > 0 && size.height > 0 {
+            renderkit.resize(size);
+            app.resize(renderkit, size);
+        }
+    }
+}
@@ -0,0 +1,352 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+
+use super::recorder::RecordingConfig;
+use super::renderkit::adapter::AdapterPolicy;
+use super::time::FrameRate;
+
+const USAGE: &str = "usage: <binary> [adapters] [options]
+
+  --config <file.toml|file.ron>
+  --title <text>  --width <px>  --height <px>
+  --fullscreen <windowed|borderless|exclusive>
+  --present-mode <mode>  --max-fps <n|uncapped>  --tick-rate <hz>
+  --backend <name>  --power-preference <low|high>  --adapter <name>
+  --device-type <type>  --fallback-adapter  --msaa <samples>
+  --log-level <level>  --asset-path <dir>
+  --record <dir|file.y4m|->  --frames <n>  --fps <n>
+
+Run as `<binary> adapters` to list the GPU adapters instead of starting.";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FullscreenMode {
+    Windowed,
+    // Covers the monitor with a borderless window at desktop resolution.
+    Borderless,
+    // Switches the monitor to the video mode closest to `width` x `height`.
+    Exclusive,
+}
+
+#[derive(Clone)]
+pub struct EngineConfig {
+    pub title: String,
+    pub width: u32,
+    pub height: u32,
+    pub fullscreen: FullscreenMode,
+    pub present_mode: wgpu::PresentMode,
+    pub backends: wgpu::Backends,
+    pub power_preference: wgpu::PowerPreference,
+    pub adapter: AdapterPolicy,
+    pub msaa: u32,
+    pub log_level: log::LevelFilter,
+    // Length of one `App::fixed_update` step.
+    pub fixed_timestep: Duration,
+    pub frame_rate: FrameRate,
+    // Asset directories searched before the default ones, highest priority
+    // first. See `resource::default_asset_roots`.
+    pub asset_paths: Vec<PathBuf>,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            title: String::from("rmagic"),
+            width: 1280,
+            height: 720,
+            fullscreen: FullscreenMode::Windowed,
+            present_mode: wgpu::PresentMode::Fifo,
+            backends: wgpu::Backends::all(),
+            power_preference: wgpu::PowerPreference::default(),
+            adapter: AdapterPolicy::default(),
+            msaa: 1,
+            log_level: log::LevelFilter::Error,
+            fixed_timestep: Duration::from_secs_f64(1.0 / 60.0),
+            frame_rate: FrameRate::Capped(60),
+            asset_paths: Vec::new(),
+        }
+    }
+}
+
+// What a config file may contain. Everything is optional and falls back to
+// the defaults, while enum-like settings are kept as strings so they go
+// through the same parsing and error messages as the command line.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    title: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    fullscreen: Option<String>,
+    present_mode: Option<String>,
+    backend: Option<String>,
+    power_preference: Option<String>,
+    adapter: Option<String>,
+    device_type: Option<String>,
+    force_fallback_adapter: Option<bool>,
+    msaa: Option<u32>,
+    log_level: Option<String>,
+    tick_rate: Option<u32>,
+    max_fps: Option<MaxFps>,
+    asset_paths: Option<Vec<PathBuf>>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MaxFps {
+    Number(u32),
+    Text(String),
+}
+
+impl EngineConfig {
+    // Builds the config from defaults, then the file passed with
+    // `--config <file.toml|file.ron>` if any, then the remaining flags:
+    // `--title`, `--width`, `--height`, `--fullscreen`, `--present-mode`,
+    // `--backend`, `--power-preference`, `--adapter`, `--device-type`,
+    // `--fallback-adapter`, `--msaa`, `--log-level`, `--tick-rate`,
+    // `--max-fps` and `--asset-path`. The recording flags are left for
+    // `RecordingConfig`, anything else is an error.
+    pub fn from_args() -> anyhow::Result<Self> {
+        Self::from_arg_list(std::env::args().skip(1).collect())
+    }
+
+    fn from_arg_list(args: Vec<String>) -> anyhow::Result<Self> {
+        let config_path = args.iter().position(|arg| arg == "--config").map(|i| {
+            args.get(i + 1).map(PathBuf::from).context("--config expects a value")
+        });
+        let mut config = match config_path {
+            Some(path) => Self::load(path?)?,
+            None => EngineConfig::default(),
+        };
+
+        // Directories given on the command line come before the config file's.
+        let mut asset_paths = Vec::new();
+        let mut args = args.into_iter().peekable();
+        // The subcommand is handled by `Engine::run`.
+        args.next_if(|arg| arg == "adapters");
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().with_context(|| format!("{} expects a value", name));
+            match arg.as_str() {
+                "--title" => config.title = value("--title")?,
+                "--width" => config.width = parse_number(&value("--width")?).context("invalid --width")?,
+                "--height" => config.height = parse_number(&value("--height")?).context("invalid --height")?,
+                "--fullscreen" => {
+                    config.fullscreen = parse_fullscreen(&value("--fullscreen")?).context("invalid --fullscreen")?
+                }
+                "--present-mode" => {
+                    config.present_mode = parse_present_mode(&value("--present-mode")?).context("invalid --present-mode")?
+                }
+                "--backend" => config.backends = parse_backends(&value("--backend")?).context("invalid --backend")?,
+                "--power-preference" => {
+                    config.power_preference =
+                        parse_power_preference(&value("--power-preference")?).context("invalid --power-preference")?
+                }
+                "--adapter" => config.adapter.name = Some(value("--adapter")?),
+                "--device-type" => {
+                    config.adapter.device_type =
+                        Some(parse_device_type(&value("--device-type")?).context("invalid --device-type")?)
+                }
+                "--fallback-adapter" => config.adapter.force_fallback = true,
+                "--msaa" => config.msaa = parse_number(&value("--msaa")?).context("invalid --msaa")?,
+                "--log-level" => config.log_level = parse_log_level(&value("--log-level")?).context("invalid --log-level")?,
+                "--tick-rate" => {
+                    config.fixed_timestep = parse_tick_rate(parse_number(&value("--tick-rate")?).context("invalid --tick-rate")?)
+                        .context("invalid --tick-rate")?
+                }
+                "--max-fps" => config.frame_rate = FrameRate::parse(&value("--max-fps")?).context("invalid --max-fps")?,
+                "--asset-path" => asset_paths.push(PathBuf::from(value("--asset-path")?)),
+                // Read before the other flags.
+                "--config" => {
+                    value("--config")?;
+                }
+                flag if RecordingConfig::FLAGS.contains(&flag) => {
+                    value(flag)?;
+                }
+                _ => bail!("unknown option {}\n\n{}", arg, USAGE),
+            }
+        }
+        config.asset_paths.splice(0..0, asset_paths);
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Reads a TOML or RON file, picked by extension, on top of the defaults.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        let file: ConfigFile = match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => toml::from_str(&text).map_err(anyhow::Error::from),
+            // Let RON files write `title: "..."` rather than `title: Some("...")`.
+            Some("ron") => ron::Options::default()
+                .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME)
+                .from_str(&text)
+                .map_err(anyhow::Error::from),
+            _ => bail!("config file {} must have a .toml or .ron extension", path.display()),
+        }
+        .with_context(|| format!("failed to parse config file {}", path.display()))?;
+
+        let mut config = EngineConfig::default();
+        config.apply_file(file).with_context(|| format!("invalid config file {}", path.display()))?;
+        // Relative asset paths are relative to the config file, not to
+        // wherever the engine happens to be started from.
+        if let Some(dir) = path.parent() {
+            config.asset_paths = config.asset_paths.iter().map(|asset_path| dir.join(asset_path)).collect();
+        }
+        config.validate().with_context(|| format!("invalid config file {}", path.display()))?;
+        Ok(config)
+    }
+
+    fn apply_file(&mut self, file: ConfigFile) -> anyhow::Result<()> {
+        if let Some(title) = file.title {
+            self.title = title;
+        }
+        if let Some(width) = file.width {
+            self.width = width;
+        }
+        if let Some(height) = file.height {
+            self.height = height;
+        }
+        if let Some(fullscreen) = file.fullscreen {
+            self.fullscreen = parse_fullscreen(&fullscreen).context("invalid `fullscreen`")?;
+        }
+        if let Some(present_mode) = file.present_mode {
+            self.present_mode = parse_present_mode(&present_mode).context("invalid `present_mode`")?;
+        }
+        if let Some(backend) = file.backend {
+            self.backends = parse_backends(&backend).context("invalid `backend`")?;
+        }
+        if let Some(power_preference) = file.power_preference {
+            self.power_preference = parse_power_preference(&power_preference).context("invalid `power_preference`")?;
+        }
+        if let Some(name) = file.adapter {
+            self.adapter.name = Some(name);
+        }
+        if let Some(device_type) = file.device_type {
+            self.adapter.device_type = Some(parse_device_type(&device_type).context("invalid `device_type`")?);
+        }
+        if let Some(force_fallback) = file.force_fallback_adapter {
+            self.adapter.force_fallback = force_fallback;
+        }
+        if let Some(msaa) = file.msaa {
+            self.msaa = msaa;
+        }
+        if let Some(log_level) = file.log_level {
+            self.log_level = parse_log_level(&log_level).context("invalid `log_level`")?;
+        }
+        if let Some(tick_rate) = file.tick_rate {
+            self.fixed_timestep = parse_tick_rate(tick_rate).context("invalid `tick_rate`")?;
+        }
+        match file.max_fps {
+            Some(MaxFps::Number(fps)) => self.frame_rate = FrameRate::parse(&fps.to_string()).context("invalid `max_fps`")?,
+            Some(MaxFps::Text(text)) => self.frame_rate = FrameRate::parse(&text).context("invalid `max_fps`")?,
+            None => {}
+        }
+        if let Some(asset_paths) = file.asset_paths {
+            self.asset_paths = asset_paths;
+        }
+        Ok(())
+    }
+
+    // Checks the settings that don't depend on the hardware. Whether the GPU
+    // supports the MSAA count or present mode is checked once it's created.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.title.trim().is_empty() {
+            bail!("the window title must not be empty");
+        }
+        if self.width == 0 || self.height == 0 {
+            bail!("window size must be non-zero, got {}x{}", self.width, self.height);
+        }
+        if ![1, 4].contains(&self.msaa) {
+            bail!("MSAA sample count must be 1 or 4, got {}", self.msaa);
+        }
+        Ok(())
+    }
+}
+
+fn parse_number(value: &str) -> anyhow::Result<u32> {
+    value.parse().with_context(|| format!("expected a number, got {:?}", value))
+}
+
+fn parse_tick_rate(rate: u32) -> anyhow::Result<Duration> {
+    if rate == 0 {
+        bail!("tick rate must be greater than zero");
+    }
+    Ok(Duration::from_secs_f64(1.0 / rate as f64))
+}
+
+fn parse_fullscreen(value: &str) -> anyhow::Result<FullscreenMode> {
+    Ok(match value.to_lowercase().as_str() {
+        "windowed" | "off" => FullscreenMode::Windowed,
+        "borderless" => FullscreenMode::Borderless,
+        "exclusive" => FullscreenMode::Exclusive,
+        _ => bail!("unknown fullscreen mode {:?}, expected windowed, borderless or exclusive", value),
+    })
+}
+
+fn parse_present_mode(value: &str) -> anyhow::Result<wgpu::PresentMode> {
+    Ok(match value.to_lowercase().replace('-', "_").as_str() {
+        "vsync" | "auto_vsync" => wgpu::PresentMode::AutoVsync,
+        "no_vsync" | "auto_no_vsync" => wgpu::PresentMode::AutoNoVsync,
+        "fifo" => wgpu::PresentMode::Fifo,
+        "fifo_relaxed" => wgpu::PresentMode::FifoRelaxed,
+        "immediate" => wgpu::PresentMode::Immediate,
+        "mailbox" => wgpu::PresentMode::Mailbox,
+        _ => bail!(
+            "unknown present mode {:?}, expected vsync, no_vsync, fifo, fifo_relaxed, immediate or mailbox",
+            value
+        ),
+    })
+}
+
+// Accepts a comma separated list such as "vulkan,dx12".
+fn parse_backends(value: &str) -> anyhow::Result<wgpu::Backends> {
+    let mut backends = wgpu::Backends::empty();
+    for name in value.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+        backends |= match name.to_lowercase().as_str() {
+            "all" => wgpu::Backends::all(),
+            "primary" => wgpu::Backends::PRIMARY,
+            "secondary" => wgpu::Backends::SECONDARY,
+            "vulkan" | "vk" => wgpu::Backends::VULKAN,
+            "metal" | "mtl" => wgpu::Backends::METAL,
+            "dx12" | "d3d12" => wgpu::Backends::DX12,
+            "dx11" | "d3d11" => wgpu::Backends::DX11,
+            "gl" | "opengl" | "gles" => wgpu::Backends::GL,
+            _ => bail!(
+                "unknown backend {:?}, expected all, primary, secondary, vulkan, metal, dx12, dx11 or gl",
+                name
+            ),
+        };
+    }
+    if backends.is_empty() {
+        bail!("no backend given");
+    }
+    Ok(backends)
+}
+
+fn parse_power_preference(value: &str) -> anyhow::Result<wgpu::PowerPreference> {
+    Ok(match value.to_lowercase().replace('-', "_").as_str() {
+        "low" | "low_power" => wgpu::PowerPreference::LowPower,
+        "high" | "high_performance" => wgpu::PowerPreference::HighPerformance,
+        _ => bail!("unknown power preference {:?}, expected low_power or high_performance", value),
+    })
+}
+
+fn parse_device_type(value: &str) -> anyhow::Result<wgpu::DeviceType> {
+    Ok(match value.to_lowercase().replace('-', "_").as_str() {
+        "discrete" | "discrete_gpu" => wgpu::DeviceType::DiscreteGpu,
+        "integrated" | "integrated_gpu" => wgpu::DeviceType::IntegratedGpu,
+        "virtual" | "virtual_gpu" => wgpu::DeviceType::VirtualGpu,
+        "cpu" => wgpu::DeviceType::Cpu,
+        _ => bail!("unknown device type {:?}, expected discrete, integrated, virtual or cpu", value),
+    })
+}
+
+fn parse_log_level(value: &str) -> anyhow::Result<log::LevelFilter> {
+    value
+        .parse()
+        .map_err(|_| anyhow::anyhow!("unknown log level {:?}, expected off, error, warn, info, debug or trace", value))
+}
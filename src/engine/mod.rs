@@ -1,3 +1,10 @@
 
+pub mod app;
+pub mod config;
 pub mod renderkit;
-pub mod resource;
\ No newline at end of file
+pub mod resource;
+pub mod recorder;
+pub mod time;
+
+pub use app::{App, Engine};
+pub use config::EngineConfig;
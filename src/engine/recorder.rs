@@ -0,0 +1,157 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+
+pub enum RecordingOutput {
+    // Numbered PNGs written into a directory.
+    Png(PathBuf),
+    // A YUV4MPEG2 stream, to a file or to stdout when the path is "-", which
+    // can be piped straight into ffmpeg or x264.
+    Y4m(PathBuf),
+}
+
+pub struct RecordingConfig {
+    pub output: RecordingOutput,
+    pub frames: u32,
+    pub fps: u32,
+}
+
+impl RecordingConfig {
+    // The flags read here, each followed by a value.
+    pub const FLAGS: [&'static str; 3] = ["--record", "--frames", "--fps"];
+
+    // Picks up `--record <dir|file.y4m|->`, `--frames <n>` and `--fps <n>`
+    // from the command line. Returns `None` unless `--record` was given.
+    pub fn from_args() -> anyhow::Result<Option<Self>> {
+        let mut output = None;
+        let mut frames = 300;
+        let mut fps = 60;
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().with_context(|| format!("{} expects a value", name));
+            match arg.as_str() {
+                "--record" => {
+                    let path = PathBuf::from(value("--record")?);
+                    let is_y4m = path.as_os_str() == "-" || path.extension().is_some_and(|e| e == "y4m");
+                    output = Some(if is_y4m { RecordingOutput::Y4m(path) } else { RecordingOutput::Png(path) });
+                }
+                "--frames" => frames = value("--frames")?.parse().context("--frames expects a number")?,
+                "--fps" => fps = value("--fps")?.parse().context("--fps expects a number")?,
+                _ => {}
+            }
+        }
+
+        // A frame is written before checking whether the recording is done.
+        if frames == 0 {
+            bail!("--frames must be greater than zero");
+        }
+        if fps == 0 {
+            bail!("--fps must be greater than zero");
+        }
+
+        Ok(output.map(|output| RecordingConfig { output, frames, fps }))
+    }
+}
+
+enum Sink {
+    Png(PathBuf),
+    Y4m(Box<dyn Write>, bool),
+}
+
+// Records a fixed number of frames, each of which represents exactly
+// `1 / fps` seconds of simulated time no matter how long it took to render.
+pub struct Recorder {
+    config: RecordingConfig,
+    sink: Sink,
+    frame: u32,
+}
+
+impl Recorder {
+    pub fn new(config: RecordingConfig) -> anyhow::Result<Self> {
+        let sink = match &config.output {
+            RecordingOutput::Png(dir) => {
+                std::fs::create_dir_all(dir)
+                    .with_context(|| format!("failed to create recording directory {}", dir.display()))?;
+                Sink::Png(dir.clone())
+            }
+            RecordingOutput::Y4m(path) => {
+                let writer: Box<dyn Write> = if path.as_os_str() == "-" {
+                    Box::new(BufWriter::new(std::io::stdout()))
+                } else {
+                    let file = File::create(path)
+                        .with_context(|| format!("failed to create recording file {}", path.display()))?;
+                    Box::new(BufWriter::new(file))
+                };
+                Sink::Y4m(writer, false)
+            }
+        };
+
+        Ok(Recorder {
+            config,
+            sink,
+            frame: 0,
+        })
+    }
+
+    pub fn timestep(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.config.fps as f64)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.config.frames
+    }
+
+    pub fn write_frame(&mut self, image: &image::RgbaImage) -> anyhow::Result<()> {
+        match &mut self.sink {
+            Sink::Png(dir) => {
+                let path = dir.join(format!("frame_{:05}.png", self.frame));
+                image.save_with_format(&path, image::ImageFormat::Png)
+                    .with_context(|| format!("failed to write {}", path.display()))?;
+            }
+            Sink::Y4m(writer, header_written) => {
+                if !*header_written {
+                    // 4:4:4 avoids having to deal with odd window sizes when
+                    // subsampling chroma.
+                    writeln!(writer, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", image.width(), image.height(), self.config.fps)?;
+                    *header_written = true;
+                }
+                writer.write_all(b"FRAME\n")?;
+                writer.write_all(&rgba_to_yuv444(image))?;
+            }
+        }
+
+        self.frame += 1;
+        if self.is_finished() {
+            self.finish()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        if let Sink::Y4m(writer, _) = &mut self.sink {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+// Planar limited range BT.601, which is what Y4M consumers assume by default.
+fn rgba_to_yuv444(image: &image::RgbaImage) -> Vec<u8> {
+    let plane = (image.width() * image.height()) as usize;
+    let mut data = vec![0; plane * 3];
+    for (i, pixel) in image.pixels().enumerate() {
+        let [r, g, b, _] = pixel.0;
+        let (r, g, b) = (r as f32, g as f32, b as f32);
+        let y = 16.0 + 0.257 * r + 0.504 * g + 0.098 * b;
+        let u = 128.0 - 0.148 * r - 0.291 * g + 0.439 * b;
+        let v = 128.0 + 0.439 * r - 0.368 * g - 0.071 * b;
+        data[i] = y.round().clamp(0.0, 255.0) as u8;
+        data[plane + i] = u.round().clamp(0.0, 255.0) as u8;
+        data[plane * 2 + i] = v.round().clamp(0.0, 255.0) as u8;
+    }
+    data
+}
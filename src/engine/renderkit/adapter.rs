@@ -0,0 +1,133 @@
+use std::fmt::Write;
+
+use anyhow::bail;
+
+// How `GPUHandle` picks an adapter when several are available. Adapters are
+// first filtered by name and fallback, then ranked by device type.
+#[derive(Clone, Debug, Default)]
+pub struct AdapterPolicy {
+    // Case-insensitive substring of the adapter name, e.g. "nvidia".
+    pub name: Option<String>,
+    // Device type to try first, before the order implied by the power preference.
+    pub device_type: Option<wgpu::DeviceType>,
+    // Only consider software adapters, e.g. to test without a GPU.
+    pub force_fallback: bool,
+}
+
+// Picks the best adapter according to `policy`. When a surface is given,
+// adapters that can't present to it are skipped.
+pub fn select(
+    mut adapters: Vec<wgpu::Adapter>,
+    policy: &AdapterPolicy,
+    power_preference: wgpu::PowerPreference,
+    surface: Option<&wgpu::Surface>,
+) -> anyhow::Result<wgpu::Adapter> {
+    let index = best(&adapters, policy, power_preference, surface)?;
+    Ok(adapters.swap_remove(index))
+}
+
+fn best(
+    adapters: &[wgpu::Adapter],
+    policy: &AdapterPolicy,
+    power_preference: wgpu::PowerPreference,
+    surface: Option<&wgpu::Surface>,
+) -> anyhow::Result<usize> {
+    if adapters.is_empty() {
+        bail!("no GPU adapters found, check that a Vulkan, Metal, DirectX or OpenGL driver is installed");
+    }
+
+    // `min_by_key` keeps the first of equally ranked adapters, i.e. the
+    // platform's own ordering.
+    let best = adapters
+        .iter()
+        .enumerate()
+        .filter(|(_, adapter)| surface.is_none_or(|surface| adapter.is_surface_supported(surface)))
+        .filter(|(_, adapter)| matches_policy(&adapter.get_info(), policy))
+        .min_by_key(|(_, adapter)| rank(&adapter.get_info(), policy, power_preference));
+
+    match best {
+        Some((index, _)) => Ok(index),
+        None => {
+            let available = adapters.iter().map(summary).collect::<Vec<_>>().join("\n  ");
+            bail!(
+                "no GPU adapter matches {}, available adapters are:\n  {}",
+                describe_policy(policy, surface.is_some()),
+                available
+            )
+        }
+    }
+}
+
+fn matches_policy(info: &wgpu::AdapterInfo, policy: &AdapterPolicy) -> bool {
+    if policy.force_fallback && info.device_type != wgpu::DeviceType::Cpu {
+        return false;
+    }
+    match &policy.name {
+        Some(name) => info.name.to_lowercase().contains(&name.to_lowercase()),
+        None => true,
+    }
+}
+
+fn rank(info: &wgpu::AdapterInfo, policy: &AdapterPolicy, power_preference: wgpu::PowerPreference) -> (bool, u32) {
+    use wgpu::DeviceType::*;
+    let preferred = policy.device_type.is_none_or(|device_type| device_type == info.device_type);
+    let order: [wgpu::DeviceType; 5] = match power_preference {
+        wgpu::PowerPreference::HighPerformance => [DiscreteGpu, IntegratedGpu, VirtualGpu, Other, Cpu],
+        wgpu::PowerPreference::LowPower => [IntegratedGpu, DiscreteGpu, VirtualGpu, Other, Cpu],
+    };
+    let position = order.iter().position(|device_type| *device_type == info.device_type).unwrap_or(order.len());
+    (!preferred, position as u32)
+}
+
+fn describe_policy(policy: &AdapterPolicy, needs_surface: bool) -> String {
+    let mut parts = Vec::new();
+    if let Some(name) = &policy.name {
+        parts.push(format!("name {:?}", name));
+    }
+    if policy.force_fallback {
+        parts.push(String::from("fallback (CPU) only"));
+    }
+    if needs_surface {
+        parts.push(String::from("presenting to the window"));
+    }
+    if parts.is_empty() {
+        String::from("the selection policy")
+    } else {
+        parts.join(", ")
+    }
+}
+
+fn summary(adapter: &wgpu::Adapter) -> String {
+    let info = adapter.get_info();
+    format!("{} ({:?}, {:?})", info.name, info.device_type, info.backend)
+}
+
+// A human readable listing of every adapter for the given backends, with the
+// one `select` would pick marked. There's no window at this point so surface
+// compatibility isn't taken into account.
+pub fn report(backends: wgpu::Backends, policy: &AdapterPolicy, power_preference: wgpu::PowerPreference) -> String {
+    let instance = wgpu::Instance::new(backends);
+    let adapters = instance.enumerate_adapters(backends).collect::<Vec<_>>();
+    if adapters.is_empty() {
+        return format!("no adapters found for backends {:?}\n", backends);
+    }
+
+    let selected = best(&adapters, policy, power_preference, None).ok();
+
+    let mut out = String::new();
+    for (i, adapter) in adapters.iter().enumerate() {
+        let info = adapter.get_info();
+        let marker = if selected == Some(i) { "  <- selected" } else { "" };
+        writeln!(out, "[{}] {}{}", i, summary(adapter), marker).ok();
+        writeln!(out, "    vendor: {:#06x}, device: {:#06x}", info.vendor, info.device).ok();
+        let driver = format!("{} {}", info.driver, info.driver_info);
+        writeln!(out, "    driver: {}", if driver.trim().is_empty() { "unknown" } else { driver.trim() }).ok();
+        writeln!(out, "    features: {:?}", adapter.features()).ok();
+        let limits = format!("{:#?}", adapter.limits()).replace('\n', "\n    ");
+        writeln!(out, "    limits: {}", limits).ok();
+    }
+    if selected.is_none() {
+        writeln!(out, "no adapter matches {}", describe_policy(policy, false)).ok();
+    }
+    out
+}
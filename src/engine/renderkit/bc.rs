@@ -0,0 +1,167 @@
+// BC1 and BC3 block compression. Encoding is done by the asset cooker and is
+// simple rather than optimal, decoding is the fallback for devices without
+// `TEXTURE_COMPRESSION_BC`.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockFormat {
+    // Opaque RGB, 8 bytes per 4x4 block.
+    Bc1,
+    // RGB with interpolated alpha, 16 bytes per 4x4 block.
+    Bc3,
+}
+
+impl BlockFormat {
+    pub fn block_size(self) -> usize {
+        match self {
+            BlockFormat::Bc1 => 8,
+            BlockFormat::Bc3 => 16,
+        }
+    }
+}
+
+// Compresses tightly packed RGBA8 pixels. Blocks hanging over the edge of
+// the image repeat its last row and column.
+pub fn encode(format: BlockFormat, rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let (blocks_x, blocks_y) = (width.div_ceil(4), height.div_ceil(4));
+    let mut out = Vec::with_capacity((blocks_x * blocks_y) as usize * format.block_size());
+    for by in 0..blocks_y {
+        for bx in 0..blocks_x {
+            let mut block = [[0u8; 4]; 16];
+            for (i, pixel) in block.iter_mut().enumerate() {
+                let x = (bx * 4 + i as u32 % 4).min(width - 1);
+                let y = (by * 4 + i as u32 / 4).min(height - 1);
+                let offset = ((y * width + x) * 4) as usize;
+                pixel.copy_from_slice(&rgba[offset..offset + 4]);
+            }
+            if format == BlockFormat::Bc3 {
+                encode_alpha(&block, &mut out);
+            }
+            encode_color(&block, &mut out);
+        }
+    }
+    out
+}
+
+pub fn decode(format: BlockFormat, blocks: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let blocks_x = width.div_ceil(4);
+    let mut rgba = vec![0; (width * height * 4) as usize];
+    for (index, block) in blocks.chunks_exact(format.block_size()).enumerate() {
+        let (bx, by) = (index as u32 % blocks_x, index as u32 / blocks_x);
+        let (alpha, color) = match format {
+            BlockFormat::Bc1 => ([255; 16], block),
+            BlockFormat::Bc3 => (decode_alpha(&block[..8]), &block[8..]),
+        };
+        let colors = decode_color(color);
+        for i in 0..16 {
+            let (x, y) = (bx * 4 + i as u32 % 4, by * 4 + i as u32 / 4);
+            if x >= width || y >= height {
+                continue;
+            }
+            let offset = ((y * width + x) * 4) as usize;
+            rgba[offset..offset + 3].copy_from_slice(&colors[i][..3]);
+            rgba[offset + 3] = alpha[i];
+        }
+    }
+    rgba
+}
+
+fn to_565(color: [u8; 3]) -> u16 {
+    ((color[0] as u16 >> 3) << 11) | ((color[1] as u16 >> 2) << 5) | (color[2] as u16 >> 3)
+}
+
+fn from_565(color: u16) -> [u8; 3] {
+    let (r, g, b) = ((color >> 11) & 31, (color >> 5) & 63, color & 31);
+    [(r << 3 | r >> 2) as u8, (g << 2 | g >> 4) as u8, (b << 3 | b >> 2) as u8]
+}
+
+fn palette(c0: u16, c1: u16) -> [[u8; 3]; 4] {
+    let (a, b) = (from_565(c0), from_565(c1));
+    let mix = |wa: u16, wb: u16, div: u16| {
+        [0, 1, 2].map(|i| ((a[i] as u16 * wa + b[i] as u16 * wb) / div) as u8)
+    };
+    if c0 > c1 {
+        [a, b, mix(2, 1, 3), mix(1, 2, 3)]
+    } else {
+        [a, b, mix(1, 1, 2), [0, 0, 0]]
+    }
+}
+
+// Uses the corners of the block's bounding box in RGB as endpoints.
+fn encode_color(block: &[[u8; 4]; 16], out: &mut Vec<u8>) {
+    let mut min = [255u8; 3];
+    let mut max = [0u8; 3];
+    for pixel in block {
+        for i in 0..3 {
+            min[i] = min[i].min(pixel[i]);
+            max[i] = max[i].max(pixel[i]);
+        }
+    }
+    let (mut c0, mut c1) = (to_565(max), to_565(min));
+    // Always use four colour mode, which needs c0 > c1.
+    if c0 < c1 {
+        std::mem::swap(&mut c0, &mut c1);
+    }
+    let colors = palette(c0, c1);
+
+    let mut indices = 0u32;
+    if c0 != c1 {
+        for (i, pixel) in block.iter().enumerate() {
+            let distance = |color: &[u8; 3]| (0..3).map(|c| (color[c] as i32 - pixel[c] as i32).pow(2)).sum::<i32>();
+            let best = (0..4).min_by_key(|&index| distance(&colors[index])).unwrap() as u32;
+            indices |= best << (i * 2);
+        }
+    }
+    out.extend_from_slice(&c0.to_le_bytes());
+    out.extend_from_slice(&c1.to_le_bytes());
+    out.extend_from_slice(&indices.to_le_bytes());
+}
+
+fn decode_color(block: &[u8]) -> [[u8; 3]; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    let colors = palette(c0, c1);
+    std::array::from_fn(|i| colors[(indices >> (i * 2) & 3) as usize])
+}
+
+fn alpha_palette(a0: u8, a1: u8) -> [u8; 8] {
+    let (a, b) = (a0 as u16, a1 as u16);
+    if a0 > a1 {
+        std::array::from_fn(|i| match i {
+            0 => a0,
+            1 => a1,
+            _ => ((a * (8 - i as u16) + b * (i as u16 - 1)) / 7) as u8,
+        })
+    } else {
+        std::array::from_fn(|i| match i {
+            0 => a0,
+            1 => a1,
+            6 => 0,
+            7 => 255,
+            _ => ((a * (6 - i as u16) + b * (i as u16 - 1)) / 5) as u8,
+        })
+    }
+}
+
+fn encode_alpha(block: &[[u8; 4]; 16], out: &mut Vec<u8>) {
+    let a0 = block.iter().map(|pixel| pixel[3]).max().unwrap();
+    let a1 = block.iter().map(|pixel| pixel[3]).min().unwrap();
+    let alphas = alpha_palette(a0, a1);
+
+    let mut indices = 0u64;
+    for (i, pixel) in block.iter().enumerate() {
+        let best = (0..8).min_by_key(|&index| (alphas[index] as i32 - pixel[3] as i32).abs()).unwrap() as u64;
+        indices |= best << (i * 3);
+    }
+    out.push(a0);
+    out.push(a1);
+    out.extend_from_slice(&indices.to_le_bytes()[..6]);
+}
+
+fn decode_alpha(block: &[u8]) -> [u8; 16] {
+    let alphas = alpha_palette(block[0], block[1]);
+    let mut bits = [0; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bits);
+    std::array::from_fn(|i| alphas[(indices >> (i * 3) & 7) as usize])
+}
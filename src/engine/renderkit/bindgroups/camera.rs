@@ -1,32 +1,44 @@
-use wgpu::{Buffer, RenderPass};
+use wgpu::Buffer;
+
+use crate::engine::renderkit::{gpuerror::GpuError, gpuhandle::GPUHandle};
+
+use super::BindGroup;
 
 
 pub struct CameraBindGroup {
     pub bind_group_layout: wgpu::BindGroupLayout,
 }
 
-impl CameraBindGroup {
-    pub fn new(device: &wgpu::Device) -> Self {
+impl BindGroup for CameraBindGroup {
+    type Resource = Buffer;
+
+    const LAYOUT_ENTRIES: &'static [wgpu::BindGroupLayoutEntry] = &[
+        wgpu::BindGroupLayoutEntry {
+            count: None,
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, has_dynamic_offset: (false), min_binding_size: (None) }
+        }
+    ];
+
+    fn new(gpu: &GPUHandle) -> Result<Self, GpuError> {
         let camera_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            gpu.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("camera_bind_group_layout"),
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        count: None,
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX,
-                        ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, has_dynamic_offset: (false), min_binding_size: (None) }
-                    }
-                ]
-            });
-        CameraBindGroup {
+                entries: Self::LAYOUT_ENTRIES,
+            })?;
+        Ok(CameraBindGroup {
             bind_group_layout: camera_bind_group_layout,
-        }
+        })
+    }
+
+    fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
     }
 
-    pub fn create_bind_group(&self, camera_buffer: Buffer, device: &wgpu::Device) -> wgpu::BindGroup {
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor{
-            label: Some("camera_bind_group"),
+    fn create_bind_group(&self, camera_buffer: &Buffer, label: &str, gpu: &GPUHandle) -> Result<wgpu::BindGroup, GpuError> {
+        gpu.create_bind_group(&wgpu::BindGroupDescriptor{
+            label: Some(label),
             layout: &self.bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
@@ -34,8 +46,7 @@ impl CameraBindGroup {
                     resource: camera_buffer.as_entire_binding()
                 }
             ]
-        });
-        camera_bind_group
+        })
     }
 
 }
\ No newline at end of file
@@ -1,25 +1,80 @@
-use crate::engine::texture::Texture;
+use std::any::{Any, TypeId};
+use std::collections::{hash_map::Entry, HashMap};
 
+use super::{gpuerror::GpuError, gpuhandle::GPUHandle};
 
+// A bind group layout and how to fill it from `Resource`, e.g. a texture for
+// materials or a uniform buffer for the camera.
+pub trait BindGroup: Sized + 'static {
+    type Resource: ?Sized;
 
-trait BindGroup {
-    fn new(device: &wgpu::Device) -> Self;
-    fn create_bind_group(&self, texture: &Texture, device: &wgpu::Device) -> wgpu::BindGroup;
+    // What `layout` is created from, so it can be checked against shaders.
+    const LAYOUT_ENTRIES: &'static [wgpu::BindGroupLayoutEntry];
+
+    fn new(gpu: &GPUHandle) -> Result<Self, GpuError>;
+    fn layout(&self) -> &wgpu::BindGroupLayout;
+    // `label` names what the resource belongs to, e.g. a material and its
+    // texture, so a bad bind group can be traced back to its asset.
+    fn create_bind_group(&self, resource: &Self::Resource, label: &str, gpu: &GPUHandle) -> Result<wgpu::BindGroup, GpuError>;
 }
 
 mod camera;
 mod texture;
 
+pub use camera::CameraBindGroup;
+pub use texture::TextureBindGroup;
+
+struct Registered {
+    bind_group: Box<dyn Any>,
+    // Creates it again on a new device.
+    create: fn(&GPUHandle) -> Result<Box<dyn Any>, GpuError>,
+}
+
+// Every bind group layout the renderer knows of, by type. The engine's own are
+// always registered, others can be added with `register`.
 pub struct BindGroups {
-    pub camera: camera::CameraBindGroup,
-    pub texture: texture::TextureBindGroup,
+    registered: HashMap<TypeId, Registered>,
 }
 
 impl BindGroups {
-    pub fn new(device: &wgpu::Device) -> Self {
-        Self {
-            camera: camera::CameraBindGroup::new(device),
-            texture: texture::TextureBindGroup::new(device),
+    pub fn new(gpu: &GPUHandle) -> Result<Self, GpuError> {
+        let mut bindgroups = BindGroups {
+            registered: HashMap::new(),
+        };
+        bindgroups.register::<CameraBindGroup>(gpu)?;
+        bindgroups.register::<TextureBindGroup>(gpu)?;
+        Ok(bindgroups)
+    }
+
+    // Creates the layout of `B` unless it's registered already.
+    pub fn register<B: BindGroup>(&mut self, gpu: &GPUHandle) -> Result<(), GpuError> {
+        if let Entry::Vacant(entry) = self.registered.entry(TypeId::of::<B>()) {
+            entry.insert(Registered {
+                bind_group: Box::new(B::new(gpu)?),
+                create: |gpu| Ok(Box::new(B::new(gpu)?)),
+            });
         }
+        Ok(())
+    }
+
+    pub fn try_get<B: BindGroup>(&self) -> Option<&B> {
+        self.registered.get(&TypeId::of::<B>())?.bind_group.downcast_ref()
     }
-}
\ No newline at end of file
+
+    // Panics if `B` wasn't registered.
+    pub fn get<B: BindGroup>(&self) -> &B {
+        match self.try_get() {
+            Some(bind_group) => bind_group,
+            None => panic!("bind group {} isn't registered", std::any::type_name::<B>()),
+        }
+    }
+
+    // Creates every registered layout again on a new device, e.g. after the
+    // old one was lost.
+    pub fn recreate(&mut self, gpu: &GPUHandle) -> Result<(), GpuError> {
+        for registered in self.registered.values_mut() {
+            registered.bind_group = (registered.create)(gpu)?;
+        }
+        Ok(())
+    }
+}
@@ -1,42 +1,52 @@
-use crate::engine::renderkit::texture::Texture;
+use crate::engine::renderkit::{gpuerror::GpuError, gpuhandle::GPUHandle, texture::Texture};
+
+use super::BindGroup;
 
 
 pub struct TextureBindGroup {
     pub bind_group_layout: wgpu::BindGroupLayout,
 }
 
-impl TextureBindGroup {
-    pub fn new(device: &wgpu::Device) -> Self {
+impl BindGroup for TextureBindGroup {
+    type Resource = Texture;
+
+    const LAYOUT_ENTRIES: &'static [wgpu::BindGroupLayoutEntry] = &[
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        },
+    ];
+
+    fn new(gpu: &GPUHandle) -> Result<Self, GpuError> {
         let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
+            gpu.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: Self::LAYOUT_ENTRIES,
                 label: Some("texture_bind_group_layout"),
-            });
-        TextureBindGroup {
+            })?;
+        Ok(TextureBindGroup {
             bind_group_layout: texture_bind_group_layout,
 
-        }
+        })
+    }
+
+    fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
     }
 
-    pub fn create_bind_group(&self, texture: &Texture, device: &wgpu::Device) -> wgpu::BindGroup {
-        let texture_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+    fn create_bind_group(&self, texture: &Texture, label: &str, gpu: &GPUHandle) -> Result<wgpu::BindGroup, GpuError> {
+        gpu.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &self.bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
@@ -48,8 +58,7 @@ impl TextureBindGroup {
                     resource: wgpu::BindingResource::Sampler(&texture.sampler),
                 },
             ],
-            label: Some("texture_bind_group"),
-        });
-        texture_bind_group
+            label: Some(label),
+        })
     }
 }
\ No newline at end of file
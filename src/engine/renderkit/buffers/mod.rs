@@ -8,4 +8,5 @@ pub trait Index {
     fn desc() -> wgpu::IndexFormat;
 }
 
-pub mod modelvertex;
\ No newline at end of file
+pub mod modelvertex;
+pub mod uniform;
\ No newline at end of file
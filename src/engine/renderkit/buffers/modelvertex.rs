@@ -1,6 +1,4 @@
-use wgpu::util::DeviceExt;
-
-use crate::engine::renderkit::gpuhandle::GPUHandle;
+use crate::engine::renderkit::{gpuerror::GpuError, gpuhandle::GPUHandle};
 
 use super::Vertex;
 
@@ -8,27 +6,39 @@ use super::Vertex;
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct ModelVertex {
     pub position: [f32; 3],
-    pub normal: [f32; 3],
     pub tex_coords: [f32; 2],
+    pub normal: [f32; 3],
 }
 
+// Fails to compile if a field is moved without updating `ATTRIBS`, which
+// would read positions, texture coordinates and normals from each other.
+const _: () = {
+    use std::mem::offset_of;
+    assert!(ModelVertex::ATTRIBS[0].offset == offset_of!(ModelVertex, position) as u64);
+    assert!(ModelVertex::ATTRIBS[1].offset == offset_of!(ModelVertex, tex_coords) as u64);
+    assert!(ModelVertex::ATTRIBS[2].offset == offset_of!(ModelVertex, normal) as u64);
+};
+
 impl Vertex for ModelVertex {
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         wgpu::VertexBufferLayout {
             array_stride: std::mem::size_of::<ModelVertex>() as wgpu::BufferAddress,
             step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x2, 2 => Float32x3],
+            attributes: &Self::ATTRIBS,
         }
     }
 }
 
 impl ModelVertex {
-    pub fn new_buffer(gpu : &GPUHandle , vertices: &[Self]) -> wgpu::Buffer {
-        let vertex_buffer = gpu.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+    // `vertex_attr_array!` packs the attributes back to back in the order
+    // given, so the fields must be declared in the same order.
+    const ATTRIBS: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x2, 2 => Float32x3];
+
+    pub fn new_buffer(gpu : &GPUHandle , vertices: &[Self]) -> Result<wgpu::Buffer, GpuError> {
+        gpu.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Vertex Buffer"),
             contents: bytemuck::cast_slice(vertices),
             usage: wgpu::BufferUsages::VERTEX,
-        });
-        vertex_buffer
+        })
     }
 }
\ No newline at end of file
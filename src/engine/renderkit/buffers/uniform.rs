@@ -0,0 +1,209 @@
+use bytemuck::Pod;
+
+use crate::engine::renderkit::{bindgroups::BindGroup, gpuerror::GpuError, gpuhandle::GPUHandle};
+
+// The WGSL type of a uniform field, for checking the Rust struct is laid out
+// the way WGSL expects in the uniform address space.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WgslType {
+    Scalar,
+    Vec2,
+    Vec3,
+    Vec4,
+    Mat3x3,
+    Mat4x4,
+}
+
+impl WgslType {
+    pub const fn align(self) -> usize {
+        match self {
+            WgslType::Scalar => 4,
+            WgslType::Vec2 => 8,
+            WgslType::Vec3 | WgslType::Vec4 | WgslType::Mat3x3 | WgslType::Mat4x4 => 16,
+        }
+    }
+
+    pub const fn size(self) -> usize {
+        match self {
+            WgslType::Scalar => 4,
+            WgslType::Vec2 => 8,
+            WgslType::Vec3 => 12,
+            WgslType::Vec4 => 16,
+            // Every column is padded to a vec4.
+            WgslType::Mat3x3 => 48,
+            WgslType::Mat4x4 => 64,
+        }
+    }
+}
+
+pub struct UniformField {
+    pub name: &'static str,
+    pub offset: usize,
+    pub ty: WgslType,
+}
+
+// A struct that's uploaded to a WGSL uniform. `FIELDS` lists its fields in
+// order, with their offsets from `std::mem::offset_of!`.
+pub trait Uniform: Pod {
+    const FIELDS: &'static [UniformField];
+}
+
+// Fails to compile for a `Uniform` whose fields are misaligned for WGSL, e.g.
+// a `[f32; 3]` followed by a vec3, or a `[[f32; 3]; 3]` for a mat3x3 without
+// the padding after each column.
+const fn check_layout(fields: &[UniformField], size: usize) {
+    let mut end = 0;
+    let mut align = 16;
+    let mut i = 0;
+    while i < fields.len() {
+        let field = &fields[i];
+        if !field.offset.is_multiple_of(field.ty.align()) {
+            panic!("a uniform field isn't aligned as WGSL requires");
+        }
+        if field.offset < end {
+            panic!("a uniform field overlaps the padded size of the one before it");
+        }
+        end = field.offset + field.ty.size();
+        if field.ty.align() > align {
+            align = field.ty.align();
+        }
+        i += 1;
+    }
+    if end > size {
+        panic!("the last uniform field is smaller than its WGSL type");
+    }
+    if size != end.div_ceil(align) * align {
+        panic!("the size of a uniform struct doesn't match its WGSL size");
+    }
+}
+
+// A uniform buffer holding a `T`, with the bind group it's bound through.
+// Changes are kept on the CPU until `upload`, which is called once per frame.
+pub struct UniformBuffer<T: Uniform> {
+    value: T,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    label: String,
+    dirty: bool,
+}
+
+impl<T: Uniform> UniformBuffer<T> {
+    pub fn new<B: BindGroup<Resource = wgpu::Buffer>>(label: &str, value: T, bind_group: &B, gpu: &GPUHandle) -> Result<Self, GpuError> {
+        const { check_layout(T::FIELDS, std::mem::size_of::<T>()) };
+        let (buffer, bind_group) = Self::create(label, &value, bind_group, gpu)?;
+        Ok(UniformBuffer {
+            value,
+            buffer,
+            bind_group,
+            label: label.to_string(),
+            dirty: false,
+        })
+    }
+
+    fn create<B: BindGroup<Resource = wgpu::Buffer>>(label: &str, value: &T, bind_group: &B, gpu: &GPUHandle) -> Result<(wgpu::Buffer, wgpu::BindGroup), GpuError> {
+        let buffer = gpu.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(label),
+            contents: bytemuck::bytes_of(value),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        })?;
+        let bind_group = bind_group.create_bind_group(&buffer, label, gpu)?;
+        Ok((buffer, bind_group))
+    }
+
+    pub fn get(&self) -> &T {
+        &self.value
+    }
+
+    // Marks the buffer for upload unless `value` is what it holds already.
+    pub fn set(&mut self, value: T) {
+        if bytemuck::bytes_of(&value) != bytemuck::bytes_of(&self.value) {
+            self.value = value;
+            self.dirty = true;
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    // Writes the value to the GPU if it changed since the last upload.
+    pub fn upload(&mut self, queue: &wgpu::Queue) {
+        if self.dirty {
+            queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&self.value));
+            self.dirty = false;
+        }
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    // Creates the buffer and bind group again on a new device, with the
+    // current value.
+    pub fn restore<B: BindGroup<Resource = wgpu::Buffer>>(&mut self, bind_group: &B, gpu: &GPUHandle) -> Result<(), GpuError> {
+        (self.buffer, self.bind_group) = Self::create(&self.label, &self.value, bind_group, gpu)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const fn field(offset: usize, ty: WgslType) -> UniformField {
+        UniformField { name: "field", offset, ty }
+    }
+
+    #[test]
+    fn valid_layouts() {
+        check_layout(&[field(0, WgslType::Mat4x4), field(64, WgslType::Vec4)], 80);
+        // A scalar fits in the padding after a vec3.
+        check_layout(&[field(0, WgslType::Vec3), field(12, WgslType::Scalar)], 16);
+        check_layout(&[field(0, WgslType::Scalar), field(8, WgslType::Vec2)], 16);
+        check_layout(&[field(0, WgslType::Mat3x3), field(48, WgslType::Scalar)], 64);
+    }
+
+    #[test]
+    #[should_panic(expected = "isn't aligned")]
+    fn vec3_after_a_packed_vec3() {
+        // `[f32; 3]` followed by another puts the second at 12.
+        check_layout(&[field(0, WgslType::Vec3), field(12, WgslType::Vec3)], 32);
+    }
+
+    #[test]
+    #[should_panic(expected = "isn't aligned")]
+    fn vec2_after_a_scalar() {
+        check_layout(&[field(0, WgslType::Scalar), field(4, WgslType::Vec2)], 16);
+    }
+
+    #[test]
+    #[should_panic(expected = "overlaps")]
+    fn unpadded_mat3x3() {
+        // `[[f32; 3]; 3]` is 36 bytes, WGSL pads every column to 16.
+        check_layout(&[field(0, WgslType::Mat3x3), field(36, WgslType::Scalar)], 48);
+    }
+
+    #[test]
+    #[should_panic(expected = "smaller than its WGSL type")]
+    fn last_field_too_small() {
+        check_layout(&[field(0, WgslType::Scalar), field(16, WgslType::Mat3x3)], 52);
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't match its WGSL size")]
+    fn missing_trailing_padding() {
+        check_layout(&[field(0, WgslType::Vec4), field(16, WgslType::Vec3)], 28);
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't match its WGSL size")]
+    fn size_not_rounded_up_to_16() {
+        // Uniform structs are at least 16 byte aligned.
+        check_layout(&[field(0, WgslType::Scalar)], 4);
+    }
+}
@@ -0,0 +1,172 @@
+use anyhow::bail;
+
+// Calls `$m!(field, stricter)` for every limit, where `stricter` says whether
+// a higher (`max`) or lower (`min`) value asks more of the device.
+macro_rules! for_each_limit {
+    ($m:ident) => {
+        $m!(max_texture_dimension_1d, max);
+        $m!(max_texture_dimension_2d, max);
+        $m!(max_texture_dimension_3d, max);
+        $m!(max_texture_array_layers, max);
+        $m!(max_bind_groups, max);
+        $m!(max_dynamic_uniform_buffers_per_pipeline_layout, max);
+        $m!(max_dynamic_storage_buffers_per_pipeline_layout, max);
+        $m!(max_sampled_textures_per_shader_stage, max);
+        $m!(max_samplers_per_shader_stage, max);
+        $m!(max_storage_buffers_per_shader_stage, max);
+        $m!(max_storage_textures_per_shader_stage, max);
+        $m!(max_uniform_buffers_per_shader_stage, max);
+        $m!(max_uniform_buffer_binding_size, max);
+        $m!(max_storage_buffer_binding_size, max);
+        $m!(max_vertex_buffers, max);
+        $m!(max_vertex_attributes, max);
+        $m!(max_vertex_buffer_array_stride, max);
+        $m!(max_push_constant_size, max);
+        $m!(min_uniform_buffer_offset_alignment, min);
+        $m!(min_storage_buffer_offset_alignment, min);
+        $m!(max_inter_stage_shader_components, max);
+        $m!(max_compute_workgroup_storage_size, max);
+        $m!(max_compute_invocations_per_workgroup, max);
+        $m!(max_compute_workgroup_size_x, max);
+        $m!(max_compute_workgroup_size_y, max);
+        $m!(max_compute_workgroup_size_z, max);
+        $m!(max_compute_workgroups_per_dimension, max);
+        $m!(max_buffer_size, max);
+    };
+}
+
+// The more demanding of `a` and `b` for every limit.
+fn stricter_limits(a: &wgpu::Limits, b: &wgpu::Limits) -> wgpu::Limits {
+    let mut limits = a.clone();
+    macro_rules! pick {
+        ($field:ident, max) => {
+            limits.$field = a.$field.max(b.$field)
+        };
+        ($field:ident, min) => {
+            limits.$field = a.$field.min(b.$field)
+        };
+    }
+    for_each_limit!(pick);
+    limits
+}
+
+// The less demanding of `a` and `b` for every limit.
+fn looser_limits(a: &wgpu::Limits, b: &wgpu::Limits) -> wgpu::Limits {
+    let mut limits = a.clone();
+    macro_rules! pick {
+        ($field:ident, max) => {
+            limits.$field = a.$field.min(b.$field)
+        };
+        ($field:ident, min) => {
+            limits.$field = a.$field.max(b.$field)
+        };
+    }
+    for_each_limit!(pick);
+    limits
+}
+
+// What a subsystem needs from the device, declared before it's created.
+// Missing required features or limits fail device creation, optional ones are
+// enabled as far as the adapter allows and can be checked on `Capabilities`.
+#[derive(Clone, Debug)]
+pub struct DeviceRequirements {
+    pub features: wgpu::Features,
+    pub optional_features: wgpu::Features,
+    pub limits: wgpu::Limits,
+    pub optional_limits: Option<wgpu::Limits>,
+}
+
+impl Default for DeviceRequirements {
+    fn default() -> Self {
+        DeviceRequirements {
+            features: wgpu::Features::empty(),
+            optional_features: wgpu::Features::empty(),
+            limits: wgpu::Limits::default(),
+            optional_limits: None,
+        }
+    }
+}
+
+impl DeviceRequirements {
+    pub fn require_features(mut self, features: wgpu::Features) -> Self {
+        self.features |= features;
+        self
+    }
+
+    pub fn request_features(mut self, features: wgpu::Features) -> Self {
+        self.optional_features |= features;
+        self
+    }
+
+    pub fn require_limits(mut self, limits: wgpu::Limits) -> Self {
+        self.limits = stricter_limits(&self.limits, &limits);
+        self
+    }
+
+    pub fn request_limits(mut self, limits: wgpu::Limits) -> Self {
+        self.optional_limits = Some(match &self.optional_limits {
+            Some(optional) => stricter_limits(optional, &limits),
+            None => limits,
+        });
+        self
+    }
+
+    // Combines the requirements of two subsystems.
+    pub fn merge(mut self, other: DeviceRequirements) -> Self {
+        self = self.require_features(other.features).request_features(other.optional_features).require_limits(other.limits);
+        match other.optional_limits {
+            Some(limits) => self.request_limits(limits),
+            None => self,
+        }
+    }
+
+    // The features and limits to create the device with on `adapter`.
+    pub fn resolve(&self, adapter: &wgpu::Adapter) -> anyhow::Result<(wgpu::Features, wgpu::Limits)> {
+        let name = adapter.get_info().name;
+
+        let supported = adapter.features();
+        let missing = self.features - supported;
+        if !missing.is_empty() {
+            bail!("adapter {} doesn't support required features {:?}", name, missing);
+        }
+
+        let supported_limits = adapter.limits();
+        let mut failures = Vec::new();
+        self.limits.check_limits_with_fail_fn(&supported_limits, false, |limit, wanted, allowed| {
+            failures.push(format!("{} (needs {}, adapter allows {})", limit, wanted, allowed));
+        });
+        if !failures.is_empty() {
+            bail!("adapter {} doesn't meet required limits: {}", name, failures.join(", "));
+        }
+
+        let features = self.features | (self.optional_features & supported);
+        let limits = match &self.optional_limits {
+            Some(optional) => stricter_limits(&self.limits, &looser_limits(optional, &supported_limits)),
+            None => self.limits.clone(),
+        };
+        Ok((features, limits))
+    }
+}
+
+// What the device was actually created with, for picking fallback paths at
+// runtime, e.g. skipping wireframe rendering without `POLYGON_MODE_LINE`.
+#[derive(Clone, Debug)]
+pub struct Capabilities {
+    pub features: wgpu::Features,
+    pub limits: wgpu::Limits,
+    pub downlevel: wgpu::DownlevelCapabilities,
+}
+
+impl Capabilities {
+    pub fn new(device: &wgpu::Device, adapter: &wgpu::Adapter) -> Self {
+        Capabilities {
+            features: device.features(),
+            limits: device.limits(),
+            downlevel: adapter.get_downlevel_capabilities(),
+        }
+    }
+
+    pub fn supports(&self, features: wgpu::Features) -> bool {
+        self.features.contains(features)
+    }
+}
@@ -0,0 +1,88 @@
+use std::sync::mpsc;
+
+use anyhow::{bail, Context};
+
+use super::gpuhandle::GPUHandle;
+
+// Copies `texture` into a mappable buffer and reads it back as an RGBA image
+// of what the display shows.
+pub fn read_texture(
+    gpu: &GPUHandle,
+    texture: &wgpu::Texture,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+) -> anyhow::Result<image::RgbaImage> {
+    // The bytes are what the display shows whether or not the format is
+    // sRGB, so only the channel order needs fixing.
+    let bgra = match format {
+        wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+        wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+        _ => bail!("can't capture frames from {:?} targets", format),
+    };
+
+    // Rows in a texture -> buffer copy must be padded to a multiple of 256 bytes.
+    let unpadded_bytes_per_row = width * 4;
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+
+    let buffer = gpu.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("capture_buffer"),
+        size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    })?;
+
+    let mut encoder = gpu.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Capture Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(padded_bytes_per_row),
+                rows_per_image: std::num::NonZeroU32::new(height),
+            },
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+    gpu.queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    let (tx, rx) = mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        tx.send(result).ok();
+    });
+    gpu.device.poll(wgpu::Maintain::Wait);
+    rx.recv()
+        .context("capture buffer was dropped before it was mapped")?
+        .context("failed to map capture buffer")?;
+
+    let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+    {
+        let data = slice.get_mapped_range();
+        for row in data.chunks(padded_bytes_per_row as usize) {
+            for pixel in row[..unpadded_bytes_per_row as usize].chunks_exact(4) {
+                if bgra {
+                    pixels.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+                } else {
+                    pixels.extend_from_slice(pixel);
+                }
+            }
+        }
+    }
+    buffer.unmap();
+
+    image::RgbaImage::from_raw(width, height, pixels).context("capture buffer has the wrong size")
+}
@@ -0,0 +1,40 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum GpuError {
+    #[error("invalid {kind} {label:?}: {description}")]
+    Validation {
+        kind: &'static str,
+        label: String,
+        description: String,
+    },
+    #[error("out of GPU memory creating {kind} {label:?}")]
+    OutOfMemory { kind: &'static str, label: String },
+}
+
+// Runs `create` inside validation and out of memory error scopes, so a bad
+// shader or descriptor comes back as an error naming the object instead of
+// reaching the uncaptured error handler.
+pub fn scoped<T>(
+    device: &wgpu::Device,
+    kind: &'static str,
+    label: Option<&str>,
+    create: impl FnOnce() -> T,
+) -> Result<T, GpuError> {
+    device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let value = create();
+    let validation = pollster::block_on(device.pop_error_scope());
+    let out_of_memory = pollster::block_on(device.pop_error_scope());
+
+    let label = label.unwrap_or("<unlabeled>").to_string();
+    match validation.or(out_of_memory) {
+        None => Ok(value),
+        Some(wgpu::Error::Validation { description, .. }) => Err(GpuError::Validation {
+            kind,
+            label,
+            description,
+        }),
+        Some(wgpu::Error::OutOfMemory { .. }) => Err(GpuError::OutOfMemory { kind, label }),
+    }
+}
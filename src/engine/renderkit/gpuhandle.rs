@@ -1,51 +1,138 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use anyhow::{bail, Context};
+use log::info;
 use wgpu::{Surface, Device};
+use wgpu::util::DeviceExt;
 use winit::window::Window;
 
+use crate::engine::config::EngineConfig;
+
+use super::{adapter, capabilities::{Capabilities, DeviceRequirements}, gpuerror::{self, GpuError}};
+
 
 pub struct GPUHandle {
+    pub adapter: wgpu::Adapter,
+    pub capabilities: Capabilities,
     pub surface: Surface,
     pub device: Device,
     pub queue: wgpu::Queue,
     pub config: wgpu::SurfaceConfiguration,
+    lost: Arc<AtomicBool>,
+}
+
+// Whether `error` or anything that caused it is wgpu-core's lost device error.
+fn is_device_lost(error: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(error);
+    while let Some(error) = source {
+        if matches!(error.downcast_ref(), Some(wgpu_core::device::DeviceError::Lost)) {
+            return true;
+        }
+        source = error.source();
+    }
+    false
 }
 
 impl GPUHandle {
-    pub async fn new(window: &Window) -> Self {
+    pub async fn new(window: &Window, config: &EngineConfig, requirements: &DeviceRequirements) -> anyhow::Result<Self> {
         let size = window.inner_size();
 
-        let instance = wgpu::Instance::new(wgpu::Backends::all());
+        let instance = wgpu::Instance::new(config.backends);
         let surface = unsafe { instance.create_surface(window) };
-        let adapter = instance.request_adapter(
-            &wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            },
-        ).await.unwrap();
+        let adapters = instance.enumerate_adapters(config.backends).collect();
+        let adapter = adapter::select(adapters, &config.adapter, config.power_preference, Some(&surface))?;
+        let info = adapter.get_info();
+        info!("using adapter {} ({:?}, {:?})", info.name, info.device_type, info.backend);
+
+        let (features, limits) = requirements.resolve(&adapter)?;
         let (device, queue) = adapter.request_device(
             &wgpu::DeviceDescriptor {
                 label: None,
-                limits: wgpu::Limits::default(),
-                features: wgpu::Features::empty()
+                limits,
+                features,
             },
             None
-        ).await.unwrap();
+        ).await.context("failed to create GPU device")?;
+        let capabilities = Capabilities::new(&device, &adapter);
+
+        // wgpu has no device lost callback yet, a lost device shows up as
+        // uncaptured errors from whatever touched it next.
+        let lost = Arc::new(AtomicBool::new(false));
+        let lost_flag = lost.clone();
+        device.on_uncaptured_error(move |error| match error {
+            wgpu::Error::OutOfMemory { .. } => {
+                lost_flag.store(true, Ordering::Relaxed);
+                log::error!("GPU device out of memory, recreating it: {}", error);
+            }
+            wgpu::Error::Validation { ref source, .. } if is_device_lost(source.as_ref()) => {
+                lost_flag.store(true, Ordering::Relaxed);
+                log::error!("GPU device lost: {}", error);
+            }
+            // Whatever caused it was skipped by wgpu, the frame goes on.
+            wgpu::Error::Validation { .. } => log::error!("wgpu error: {}", error),
+        });
+
+        // The Auto modes fall back on their own, explicit ones must be supported.
+        let present_modes = surface.get_supported_present_modes(&adapter);
+        let present_mode = config.present_mode;
+        let is_auto = matches!(present_mode, wgpu::PresentMode::AutoVsync | wgpu::PresentMode::AutoNoVsync);
+        if !is_auto && !present_modes.contains(&present_mode) {
+            bail!("present mode {:?} isn't supported by this surface, available modes are {:?}", present_mode, present_modes);
+        }
+
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface.get_supported_formats(&adapter)[0],
+            format: *surface.get_supported_formats(&adapter).first().context("the surface supports no texture formats")?,
             width: size.width,
             height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
+            present_mode,
             alpha_mode: wgpu::CompositeAlphaMode::Auto,
         };
         surface.configure(&device, &config);
 
-        GPUHandle {
+        Ok(GPUHandle {
+            adapter,
+            capabilities,
             queue,
             config,
             surface,
             device,
-        }
+            lost,
+        })
+    }
+
+    pub fn create_shader_module(&self, descriptor: wgpu::ShaderModuleDescriptor) -> Result<wgpu::ShaderModule, GpuError> {
+        let label = descriptor.label;
+        gpuerror::scoped(&self.device, "shader module", label, || self.device.create_shader_module(descriptor))
+    }
+
+    pub fn create_texture(&self, descriptor: &wgpu::TextureDescriptor) -> Result<wgpu::Texture, GpuError> {
+        gpuerror::scoped(&self.device, "texture", descriptor.label, || self.device.create_texture(descriptor))
+    }
+
+    pub fn create_buffer(&self, descriptor: &wgpu::BufferDescriptor) -> Result<wgpu::Buffer, GpuError> {
+        gpuerror::scoped(&self.device, "buffer", descriptor.label, || self.device.create_buffer(descriptor))
+    }
+
+    pub fn create_buffer_init(&self, descriptor: &wgpu::util::BufferInitDescriptor) -> Result<wgpu::Buffer, GpuError> {
+        gpuerror::scoped(&self.device, "buffer", descriptor.label, || self.device.create_buffer_init(descriptor))
+    }
+
+    pub fn create_sampler(&self, descriptor: &wgpu::SamplerDescriptor) -> Result<wgpu::Sampler, GpuError> {
+        gpuerror::scoped(&self.device, "sampler", descriptor.label, || self.device.create_sampler(descriptor))
+    }
+
+    pub fn create_bind_group_layout(&self, descriptor: &wgpu::BindGroupLayoutDescriptor) -> Result<wgpu::BindGroupLayout, GpuError> {
+        gpuerror::scoped(&self.device, "bind group layout", descriptor.label, || self.device.create_bind_group_layout(descriptor))
+    }
+
+    pub fn create_bind_group(&self, descriptor: &wgpu::BindGroupDescriptor) -> Result<wgpu::BindGroup, GpuError> {
+        gpuerror::scoped(&self.device, "bind group", descriptor.label, || self.device.create_bind_group(descriptor))
+    }
+
+    pub fn is_lost(&self) -> bool {
+        self.lost.load(Ordering::Relaxed)
     }
 
     pub fn resize(&mut self, size: winit::dpi::PhysicalSize<u32>) {
@@ -54,4 +141,23 @@ impl GPUHandle {
         self.surface.configure(&self.device, &self.config);
     }
 
+    // Sample counts usable for attachments of all the given formats. wgpu only
+    // reports a single multisample flag per format, not which counts it
+    // covers, so only the x4 every adapter supports is offered.
+    pub fn supported_sample_counts(&self, formats: &[wgpu::TextureFormat]) -> Vec<u32> {
+        let multisample = formats.iter().all(|format| {
+            let flags = self.adapter.get_texture_format_features(*format).flags;
+            let mut required = wgpu::TextureFormatFeatureFlags::MULTISAMPLE;
+            if format.describe().sample_type != wgpu::TextureSampleType::Depth {
+                required |= wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE;
+            }
+            flags.contains(required)
+        });
+
+        let mut counts = vec![1];
+        if multisample {
+            counts.push(4);
+        }
+        counts
+    }
 }
\ No newline at end of file
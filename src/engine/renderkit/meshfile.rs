@@ -0,0 +1,371 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use anyhow::{bail, Context};
+
+use crate::engine::resource::{bytereader::ByteReader, SharedBytes};
+
+use super::buffers::{modelvertex::ModelVertex, Vertex};
+use super::model::{MaterialData, ObjData};
+use super::permutations::MaterialFeatures;
+
+// Cooked models, laid out so the vertex and index data can be handed to the
+// GPU straight from the (memory mapped) file. Layout, with every integer
+// little endian and strings as a u32 length followed by UTF-8:
+//
+//   header     magic "RMMS", version u32, CRC32 of the body u32, body size u64
+//   layout     vertex stride u32, attribute count u32, then per attribute
+//              its wgpu::VertexFormat u32, offset u32 and shader location u32
+//   bounds     min and max corner, 3 f32 each, around every submesh
+//   materials  count u32, per material: name, diffuse texture path,
+//              MaterialFeatures bits u32
+//   submeshes  count u32, per submesh: name, material index u32, vertex
+//              count u32, index count u32, bounds
+//   data       per submesh: its vertices, then its u32 indices
+const MAGIC: &[u8; 4] = b"RMMS";
+const VERSION: u32 = 3;
+const HEADER_SIZE: usize = 20;
+
+// An axis aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl Bounds {
+    // Contains nothing, and becomes the other box in a `union`.
+    pub const EMPTY: Bounds = Bounds {
+        min: [f32::INFINITY; 3],
+        max: [f32::NEG_INFINITY; 3],
+    };
+
+    pub fn from_points(points: impl IntoIterator<Item = [f32; 3]>) -> Bounds {
+        points.into_iter().fold(Bounds::EMPTY, |bounds, point| bounds.union(&Bounds { min: point, max: point }))
+    }
+
+    pub fn union(&self, other: &Bounds) -> Bounds {
+        Bounds {
+            min: [0, 1, 2].map(|i| self.min[i].min(other.min[i])),
+            max: [0, 1, 2].map(|i| self.max[i].max(other.max[i])),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        (0..3).any(|i| self.min[i] > self.max[i])
+    }
+}
+
+pub struct SubmeshData {
+    pub name: String,
+    pub material: usize,
+    pub bounds: Bounds,
+    // Where the submesh's vertices and indices are in the file.
+    pub vertex_range: Range<usize>,
+    pub index_range: Range<usize>,
+}
+
+impl SubmeshData {
+    pub fn index_count(&self) -> u32 {
+        (self.index_range.len() / 4) as u32
+    }
+}
+
+// A parsed mesh file. The vertex and index data stay in the file's bytes
+// and are only referenced by range.
+pub struct MeshFile {
+    pub bytes: SharedBytes,
+    pub bounds: Bounds,
+    pub materials: Vec<MaterialData>,
+    pub submeshes: Vec<SubmeshData>,
+}
+
+impl MeshFile {
+    pub fn parse(bytes: SharedBytes) -> anyhow::Result<MeshFile> {
+        let data = (*bytes).as_ref();
+        let mut reader = ByteReader::new(data);
+        if reader.take(4)? != MAGIC {
+            bail!("not a cooked mesh");
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            bail!("unsupported mesh version {}, expected {}", version, VERSION);
+        }
+        let checksum = reader.u32()?;
+        let body_size = reader.u64()? as usize;
+        let body = reader.take(body_size).context("truncated mesh")?;
+        if crc32fast::hash(body) != checksum {
+            bail!("mesh is corrupt, its checksum doesn't match");
+        }
+
+        let mut reader = ByteReader::new(body);
+        Self::check_layout(&mut reader)?;
+        let bounds = read_bounds(&mut reader)?;
+
+        let materials = (0..reader.u32()?)
+            .map(|_| {
+                let name = reader.string()?;
+                let diffuse_texture = reader.string()?;
+                let bits = reader.u32()?;
+                let features = MaterialFeatures::from_bits(bits)
+                    .with_context(|| format!("material {} has unknown features {:#x}", name, bits))?;
+                Ok(MaterialData { name, diffuse_texture, features })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let submesh_count = reader.u32()?;
+        let mut headers = Vec::new();
+        for _ in 0..submesh_count {
+            let name = reader.string()?;
+            let material = reader.u32()? as usize;
+            if material >= materials.len() {
+                bail!("submesh {} uses material {}, but there are only {}", name, material, materials.len());
+            }
+            let vertex_count = reader.u32()? as usize;
+            let index_count = reader.u32()? as usize;
+            let bounds = read_bounds(&mut reader)?;
+            headers.push((name, material, vertex_count, index_count, bounds));
+        }
+
+        let mut submeshes = Vec::new();
+        for (name, material, vertex_count, index_count, bounds) in headers {
+            let start = HEADER_SIZE + body_size - reader.remaining();
+            let vertices = reader.take(vertex_count * std::mem::size_of::<ModelVertex>())?;
+            let indices = reader.take(index_count * 4)?;
+            if indices.chunks_exact(4).any(|index| u32::from_le_bytes(index.try_into().unwrap()) as usize >= vertex_count) {
+                bail!("submesh {} has an index past its {} vertices", name, vertex_count);
+            }
+            let vertex_range = start..start + vertices.len();
+            let index_range = vertex_range.end..vertex_range.end + indices.len();
+            submeshes.push(SubmeshData { name, material, bounds, vertex_range, index_range });
+        }
+
+        Ok(MeshFile { bytes, bounds, materials, submeshes })
+    }
+
+    // Meshes are cooked for `ModelVertex`, so one cooked with a different
+    // version of it can't be used.
+    fn check_layout(reader: &mut ByteReader) -> anyhow::Result<()> {
+        let expected = ModelVertex::desc();
+        let stride = reader.u32()? as u64;
+        let attributes = (0..reader.u32()?)
+            .map(|_| Ok((reader.u32()?, reader.u32()? as u64, reader.u32()?)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let matches = stride == expected.array_stride
+            && attributes.len() == expected.attributes.len()
+            && attributes.iter().zip(expected.attributes).all(|(&(format, offset, location), attribute)| {
+                format == attribute.format as u32 && offset == attribute.offset && location == attribute.shader_location
+            });
+        if !matches {
+            bail!("mesh was cooked with a different vertex layout, cook it again");
+        }
+        Ok(())
+    }
+
+    // Converts parsed OBJ (or glTF) data, e.g. to cook it.
+    pub fn from_obj(obj: &ObjData) -> MeshFile {
+        let mut body = Vec::new();
+        let layout = ModelVertex::desc();
+        body.extend_from_slice(&(layout.array_stride as u32).to_le_bytes());
+        body.extend_from_slice(&(layout.attributes.len() as u32).to_le_bytes());
+        for attribute in layout.attributes {
+            body.extend_from_slice(&(attribute.format as u32).to_le_bytes());
+            body.extend_from_slice(&(attribute.offset as u32).to_le_bytes());
+            body.extend_from_slice(&attribute.shader_location.to_le_bytes());
+        }
+
+        let submesh_bounds = obj.meshes.iter()
+            .map(|mesh| Bounds::from_points(mesh.vertices.iter().map(|vertex| vertex.position)))
+            .collect::<Vec<_>>();
+        write_bounds(&mut body, &submesh_bounds.iter().fold(Bounds::EMPTY, |all, bounds| all.union(bounds)));
+
+        body.extend_from_slice(&(obj.materials.len() as u32).to_le_bytes());
+        for material in &obj.materials {
+            write_str(&mut body, &material.name);
+            write_str(&mut body, &material.diffuse_texture);
+            body.extend_from_slice(&material.features.bits().to_le_bytes());
+        }
+        body.extend_from_slice(&(obj.meshes.len() as u32).to_le_bytes());
+        for (mesh, bounds) in obj.meshes.iter().zip(&submesh_bounds) {
+            write_str(&mut body, &mesh.name);
+            body.extend_from_slice(&(mesh.material as u32).to_le_bytes());
+            body.extend_from_slice(&(mesh.vertices.len() as u32).to_le_bytes());
+            body.extend_from_slice(&(mesh.indices.len() as u32).to_le_bytes());
+            write_bounds(&mut body, bounds);
+        }
+        for mesh in &obj.meshes {
+            body.extend_from_slice(bytemuck::cast_slice(&mesh.vertices));
+            body.extend_from_slice(bytemuck::cast_slice(&mesh.indices));
+        }
+
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + body.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&crc32fast::hash(&body).to_le_bytes());
+        bytes.extend_from_slice(&(body.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&body);
+        Self::parse(Arc::new(bytes)).expect("a freshly written mesh file is valid")
+    }
+
+    pub fn data(&self) -> &[u8] {
+        (*self.bytes).as_ref()
+    }
+}
+
+fn write_str(out: &mut Vec<u8>, value: &str) {
+    out.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn write_bounds(out: &mut Vec<u8>, bounds: &Bounds) {
+    for value in bounds.min.iter().chain(&bounds.max) {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn read_bounds(reader: &mut ByteReader) -> anyhow::Result<Bounds> {
+    let mut values = [0.0; 6];
+    for value in &mut values {
+        *value = reader.f32()?;
+    }
+    Ok(Bounds {
+        min: [values[0], values[1], values[2]],
+        max: [values[3], values[4], values[5]],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::renderkit::model::MeshData;
+
+    fn vertex(position: [f32; 3]) -> ModelVertex {
+        ModelVertex { position, tex_coords: [position[0], position[1]], normal: [0.0, 0.0, 1.0] }
+    }
+
+    fn obj() -> ObjData {
+        ObjData {
+            meshes: vec![
+                MeshData {
+                    name: String::from("triangle"),
+                    vertices: vec![vertex([0.0, 0.0, 0.0]), vertex([1.0, 0.0, 0.0]), vertex([0.0, 2.0, 0.0])],
+                    indices: vec![0, 1, 2],
+                    material: 1,
+                },
+                MeshData {
+                    name: String::from("quad"),
+                    vertices: vec![vertex([-1.0, -1.0, -3.0]), vertex([1.0, -1.0, -3.0]), vertex([1.0, 1.0, -3.0]), vertex([-1.0, 1.0, -3.0])],
+                    indices: vec![0, 1, 2, 0, 2, 3],
+                    material: 0,
+                },
+            ],
+            materials: vec![
+                MaterialData { name: String::from("plain"), diffuse_texture: String::from("a.png"), features: MaterialFeatures::NONE },
+                MaterialData {
+                    name: String::from("masked"),
+                    diffuse_texture: String::from("textures/b.png"),
+                    features: MaterialFeatures::ALPHA_TEST | MaterialFeatures::NORMAL_MAP,
+                },
+            ],
+        }
+    }
+
+    fn bytes() -> Vec<u8> {
+        MeshFile::from_obj(&obj()).data().to_vec()
+    }
+
+    fn parse(bytes: Vec<u8>) -> anyhow::Result<MeshFile> {
+        MeshFile::parse(Arc::new(bytes))
+    }
+
+    // Writes the checksum again after the body was changed.
+    fn reseal(bytes: &mut [u8]) {
+        let checksum = crc32fast::hash(&bytes[HEADER_SIZE..]);
+        bytes[8..12].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    // Where the u32 written right after the string `name` is.
+    fn after(bytes: &[u8], name: &str) -> usize {
+        bytes.windows(name.len()).position(|window| window == name.as_bytes()).unwrap() + name.len()
+    }
+
+    #[test]
+    fn round_trip() {
+        let obj = obj();
+        let mesh = parse(bytes()).unwrap();
+
+        assert_eq!(mesh.bounds, Bounds { min: [-1.0, -1.0, -3.0], max: [1.0, 2.0, 0.0] });
+        assert_eq!(mesh.materials.len(), 2);
+        for (parsed, material) in mesh.materials.iter().zip(&obj.materials) {
+            assert_eq!(parsed.name, material.name);
+            assert_eq!(parsed.diffuse_texture, material.diffuse_texture);
+            assert_eq!(parsed.features, material.features);
+        }
+
+        assert_eq!(mesh.submeshes.len(), 2);
+        for (submesh, data) in mesh.submeshes.iter().zip(&obj.meshes) {
+            assert_eq!(submesh.name, data.name);
+            assert_eq!(submesh.material, data.material);
+            assert_eq!(submesh.index_count(), data.indices.len() as u32);
+            assert_eq!(submesh.bounds, Bounds::from_points(data.vertices.iter().map(|vertex| vertex.position)));
+            assert_eq!(&mesh.data()[submesh.vertex_range.clone()], bytemuck::cast_slice::<_, u8>(&data.vertices));
+            assert_eq!(&mesh.data()[submesh.index_range.clone()], bytemuck::cast_slice::<_, u8>(&data.indices));
+        }
+    }
+
+    #[test]
+    fn corrupt_body_fails_the_checksum() {
+        let mut bytes = bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let error = parse(bytes).err().unwrap();
+        assert!(error.to_string().contains("checksum"), "{}", error);
+    }
+
+    #[test]
+    fn truncated_files_are_rejected() {
+        let bytes = bytes();
+        for len in [0, 3, HEADER_SIZE, bytes.len() - 1] {
+            assert!(parse(bytes[..len].to_vec()).is_err(), "{} bytes were accepted", len);
+        }
+    }
+
+    #[test]
+    fn bad_headers_are_rejected() {
+        let mut magic = bytes();
+        magic[0] = b'X';
+        assert!(parse(magic).is_err());
+        let mut version = bytes();
+        version[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(parse(version).is_err());
+    }
+
+    #[test]
+    fn other_vertex_layouts_are_rejected() {
+        let mut bytes = bytes();
+        bytes[HEADER_SIZE..HEADER_SIZE + 4].copy_from_slice(&4u32.to_le_bytes());
+        reseal(&mut bytes);
+        let error = parse(bytes).err().unwrap();
+        assert!(error.to_string().contains("vertex layout"), "{}", error);
+    }
+
+    #[test]
+    fn missing_materials_are_rejected() {
+        let mut bytes = bytes();
+        let material = after(&bytes, "quad");
+        bytes[material..material + 4].copy_from_slice(&2u32.to_le_bytes());
+        reseal(&mut bytes);
+        let error = parse(bytes).err().unwrap();
+        assert!(error.to_string().contains("material 2"), "{}", error);
+    }
+
+    #[test]
+    fn indices_past_the_vertices_are_rejected() {
+        let mut bytes = bytes();
+        let last = bytes.len() - 4;
+        bytes[last..].copy_from_slice(&4u32.to_le_bytes());
+        reseal(&mut bytes);
+        let error = parse(bytes).err().unwrap();
+        assert!(error.to_string().contains("past its 4 vertices"), "{}", error);
+    }
+}
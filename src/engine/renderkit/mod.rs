@@ -1,68 +1,237 @@
 
-use std::collections::HashMap;
+use std::path::Path;
 
-use bytemuck::bytes_of;
+use anyhow::Context;
 use gpuhandle::GPUHandle;
-use wgpu::{util::DeviceExt, include_wgsl};
 use winit::window::Window;
 
-use self::{pipelinehandle::PipelineHandle, bindgroups::BindGroups};
+use crate::camera::{Camera, CameraUniform};
+use crate::engine::config::EngineConfig;
+use crate::engine::resource::{vfs, AssetServer, Handle};
 
-mod bindgroups;
-mod gpuhandle;
-mod texture;
-mod pipelinehandle;
-mod buffers;
-mod model;
+use self::{
+    capabilities::DeviceRequirements,
+    gpuerror::GpuError,
+    pipelinehandle::PipelineHandle,
+    bindgroups::{BindGroup, BindGroups, CameraBindGroup, TextureBindGroup},
+    buffers::{modelvertex::ModelVertex, uniform::UniformBuffer, Vertex},
+    model::Model,
+    texture::Texture,
+    postprocess::{PostProcess, HDR_FORMAT},
+    shader::Shader,
+    permutations::{MaterialFeatures, ShaderPermutations},
+    rendergraph::{RenderGraph, TextureDesc, TransientPool},
+};
+
+pub mod bindgroups;
+mod capture;
+pub mod adapter;
+pub mod capabilities;
+pub mod gpuerror;
+pub mod gpuhandle;
+pub mod texture;
+pub mod texturefile;
+pub mod bc;
+pub mod pipelinehandle;
+pub mod buffers;
+pub mod model;
+pub mod meshfile;
+pub mod rendergraph;
+pub mod shader;
+pub mod permutations;
+pub mod preprocessor;
+pub mod reflection;
+pub mod postprocess;
 
 pub trait Renderable {
-    fn render(&self, render_pass: &mut wgpu::RenderPass);
+    // Called once per frame before rendering, e.g. to pick up assets that
+    // finished loading.
+    fn prepare(&mut self) {}
+
+    // The material features it's drawn with, so their permutations of the
+    // scene shader are compiled before it's rendered.
+    fn material_features(&self) -> Vec<MaterialFeatures> {
+        Vec::new()
+    }
+
+    // Starts with the scene pipeline for `MaterialFeatures::NONE` set, and
+    // may switch to any other permutation in `scene`.
+    fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, scene: &'a ShaderPermutations);
+
+    // Recreates the GPU resources on a new device after the old one was lost.
+    fn restore(&mut self, gpu: &GPUHandle, bindgroups: &BindGroups) -> anyhow::Result<()>;
 }
 
+pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+pub const SCENE_SHADER: &str = "shaders/scene.wgsl";
+// The permutations of the scene shader compiled at startup, if it exists.
+pub const SCENE_PERMUTATIONS: &str = "shaders/scene.permutations";
+
 pub struct RenderKit {
-    pipeline: PipelineHandle,
+    scene: ShaderPermutations,
+    sample_count: u32,
     renderables: Vec<Box<dyn Renderable>>,
     bindgroups: BindGroups,
+    camera: UniformBuffer<CameraUniform>,
+    transients: TransientPool,
+    // What the device was created from, to create it again if it's lost.
+    config: EngineConfig,
+    requirements: DeviceRequirements,
+    pub postprocess: PostProcess,
+    pub assets: AssetServer,
     pub gpu: GPUHandle,
 }
 
 impl RenderKit {
-    pub async fn new(window: &Window) -> Self {
-        let gpu = GPUHandle::new(window).await;
+    // `requirements` are what the app needs from the device on top of the
+    // renderer's own.
+    pub async fn new(window: &Window, config: &EngineConfig, requirements: DeviceRequirements) -> anyhow::Result<Self> {
+        let requirements = requirements.merge(Self::device_requirements());
+        let gpu = GPUHandle::new(window, config, &requirements).await?;
+
+        let bindgroups = BindGroups::new(&gpu)?;
+
+        let camera = UniformBuffer::new("Camera Buffer", CameraUniform::new(), bindgroups.get::<CameraBindGroup>(), &gpu)?;
+
+        let mut assets = AssetServer::new(&gpu)?;
+        let sample_count = 1;
+        let mut scene = ShaderPermutations::new(SCENE_SHADER, Self::create_scene_pipeline, sample_count);
+        scene.prepare(MaterialFeatures::NONE, &mut assets, &gpu, &bindgroups)?;
+        if vfs::vfs().contains(SCENE_PERMUTATIONS) {
+            if let Err(e) = scene.prewarm_from_list(SCENE_PERMUTATIONS, &mut assets, &gpu, &bindgroups) {
+                log::error!("failed to prewarm the scene shader: {:?}", e);
+            }
+        }
+
+        let postprocess = PostProcess::new(&gpu, gpu.config.format, assets.load_shader(postprocess::SHADER, &gpu)?)?;
+
+        let mut renderkit = RenderKit {
+            renderables: Vec::new(),
+            bindgroups,
+            camera,
+            scene,
+            sample_count,
+            transients: TransientPool::new(),
+            config: config.clone(),
+            requirements,
+            postprocess,
+            assets,
+            gpu
+        };
+        renderkit.set_sample_count(config.msaa)?;
+        Ok(renderkit)
+    }
+
+    pub fn is_device_lost(&self) -> bool {
+        self.gpu.is_lost()
+    }
+
+    // Creates a new device after the old one was lost, e.g. by a driver reset,
+    // and rebuilds the surface, pipelines, bind groups, post chain and every
+    // renderable on it from their CPU side sources.
+    pub fn recover(&mut self, window: &Window) -> anyhow::Result<()> {
+        self.gpu = pollster::block_on(GPUHandle::new(window, &self.config, &self.requirements))
+            .context("failed to recreate the GPU device")?;
+        self.bindgroups.recreate(&self.gpu)?;
+        self.camera.restore(self.bindgroups.get::<CameraBindGroup>(), &self.gpu)?;
+        self.assets.restore(&self.gpu, &self.bindgroups)?;
+
+        // The device may have come back on a different adapter.
+        let supported = self.gpu.supported_sample_counts(&[HDR_FORMAT, DEPTH_FORMAT]);
+        if !supported.contains(&self.sample_count) {
+            log::warn!("MSAA x{} isn't supported after recovering the device, disabling it", self.sample_count);
+            self.sample_count = 1;
+        }
+        self.scene.rebuild(self.sample_count, &self.gpu, &self.bindgroups)?;
+        self.postprocess.restore(&self.gpu, self.gpu.config.format)?;
+        self.transients.clear();
+
+        for renderable in &mut self.renderables {
+            renderable.restore(&self.gpu, &self.bindgroups)?;
+        }
+        Ok(())
+    }
+
+    fn device_requirements() -> DeviceRequirements {
+        // Needed for multisampling formats beyond the guaranteed ones, and
+        // for sampling cooked textures without decompressing them first.
+        DeviceRequirements::default().request_features(
+            wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES | wgpu::Features::TEXTURE_COMPRESSION_BC,
+        )
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    // Switches MSAA on or off at runtime. Every pipeline that renders into the
+    // scene attachments is rebuilt so their sample counts stay in sync.
+    pub fn set_sample_count(&mut self, sample_count: u32) -> anyhow::Result<()> {
+        let supported = self.gpu.supported_sample_counts(&[HDR_FORMAT, DEPTH_FORMAT]);
+        if !supported.contains(&sample_count) {
+            anyhow::bail!("MSAA x{} is not supported by this adapter (supported: {:?})", sample_count, supported);
+        }
+        if sample_count == self.sample_count {
+            return Ok(());
+        }
+
+        self.scene.rebuild(sample_count, &self.gpu, &self.bindgroups)?;
+        self.sample_count = sample_count;
+        self.transients.clear();
+        Ok(())
+    }
+
+    fn create_scene_pipeline(
+        gpu: &GPUHandle,
+        bindgroups: &BindGroups,
+        shader: &Shader,
+        sample_count: u32,
+    ) -> anyhow::Result<PipelineHandle> {
+        // The bind group layouts are written by hand, so check they still
+        // match what the shader declares.
+        shader.layout().verify(0, TextureBindGroup::LAYOUT_ENTRIES).context("texture bind group doesn't match the scene shader")?;
+        shader.layout().verify(1, CameraBindGroup::LAYOUT_ENTRIES).context("camera bind group doesn't match the scene shader")?;
 
-        let bindgroups = BindGroups::new(&gpu.device);
-        
-        let shader = gpu.device.create_shader_module(include_wgsl!("../../shader.wgsl"));
         let vertex_state = wgpu::VertexState {
-            module: &shader,
+            module: &shader.module,
             entry_point: "vs_main",
-            buffers: &[],
+            buffers: &[ModelVertex::desc()],
         };
 
         let fragment_state = wgpu::FragmentState {
-            module: &shader,
+            module: &shader.module,
             entry_point: "fs_main",
             targets: &[Some(wgpu::ColorTargetState {
-                format: gpu.config.format,
+                format: HDR_FORMAT,
                 blend: Some(wgpu::BlendState::ALPHA_BLENDING),
                 write_mask: wgpu::ColorWrites::ALL,
             })],
         };
 
+        let depth_stencil = wgpu::DepthStencilState {
+            format: DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        };
+
         let pipeline = PipelineHandle::new(
-            &[&bindgroups.texture.bind_group_layout, &bindgroups.camera.bind_group_layout],
+            "scene_pipeline",
+            &[bindgroups.get::<TextureBindGroup>().layout(), bindgroups.get::<CameraBindGroup>().layout()],
             vertex_state,
             Some(fragment_state),
-            None,
+            Some(depth_stencil),
+            sample_count,
             &gpu.device,
-        );
+        )?;
+        Ok(pipeline)
+    }
 
-        RenderKit {
-            renderables: Vec::new(),
-            bindgroups,
-            pipeline,
-            gpu
-        }
+    pub fn update_camera(&mut self, camera: &Camera) {
+        let mut uniform = *self.camera.get();
+        uniform.update_view_proj(camera);
+        self.camera.set(uniform);
     }
 
     pub fn insert_renderable(&mut self, renderable: Box<dyn Renderable>) {
@@ -71,57 +240,185 @@ impl RenderKit {
 
     pub fn resize(&mut self, size: winit::dpi::PhysicalSize<u32>) {
         self.gpu.resize(size);
+        self.transients.clear();
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
         let output = self.gpu.surface.get_current_texture()?;
         let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        self.draw_frame(&view);
+        output.present();
+        Ok(())
+    }
+
+    // Renders the current frame again into an offscreen copy of the swapchain
+    // and reads it back, so captures don't depend on the surface allowing
+    // COPY_SRC.
+    pub fn capture_frame(&mut self) -> anyhow::Result<image::RgbaImage> {
+        let (width, height, format) = (self.gpu.config.width, self.gpu.config.height, self.gpu.config.format);
+        let texture = self.gpu.create_texture(&wgpu::TextureDescriptor {
+            label: Some("capture_texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        })?;
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        self.draw_frame(&view);
+        capture::read_texture(&self.gpu, &texture, width, height, format)
+    }
+
+    pub fn screenshot(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let image = self.capture_frame()?;
+        image.save_with_format(path.as_ref(), image::ImageFormat::Png)
+            .with_context(|| format!("failed to save screenshot to {}", path.as_ref().display()))?;
+        Ok(())
+    }
+
+    fn draw_frame(&mut self, target: &wgpu::TextureView) {
         let mut encoder = self.gpu.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Render Encoder")
         });
 
-        {
+        self.camera.upload(&self.gpu.queue);
+        self.postprocess.update(&self.gpu.queue);
+
+        let mut graph = RenderGraph::new();
+        let backbuffer = graph.import_texture("swapchain", target);
+        let (width, height) = (self.gpu.config.width, self.gpu.config.height);
+        let hdr = graph.create_texture("hdr", TextureDesc {
+            width,
+            height,
+            format: HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            sample_count: 1,
+        });
+        let depth = graph.create_texture("depth", TextureDesc {
+            width,
+            height,
+            format: DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            sample_count: self.sample_count,
+        });
+        // With MSAA the scene draws into a multisampled target which is
+        // resolved into the single sampled HDR texture the post chain reads.
+        let msaa = if self.sample_count > 1 {
+            Some(graph.create_texture("hdr_msaa", TextureDesc {
+                width,
+                height,
+                format: HDR_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                sample_count: self.sample_count,
+            }))
+        } else {
+            None
+        };
+
+        let scene = &self.scene;
+        let renderables = &self.renderables;
+        let camera_bind_group = self.camera.bind_group();
+        let mut scene_writes = vec![hdr, depth];
+        scene_writes.extend(msaa);
+        graph.add_pass("scene", &[], &scene_writes, move |resources, encoder| {
+            let (view, resolve_target) = match msaa {
+                Some(msaa) => (resources.texture(msaa), Some(resources.texture(hdr))),
+                None => (resources.texture(hdr), None),
+            };
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
+                    view,
+                    resolve_target,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
-                        store: true,
+                        store: msaa.is_none(),
                     }
                 })],
-                depth_stencil_attachment: None,
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: resources.texture(depth),
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: false,
+                    }),
+                    stencil_ops: None,
+                }),
             });
 
-            render_pass.set_pipeline(&self.pipeline.pipeline);
-            
+            let Some(default) = scene.get(MaterialFeatures::NONE) else { return };
+            render_pass.set_bind_group(1, camera_bind_group, &[]);
+            for renderable in renderables {
+                render_pass.set_pipeline(&default.pipeline);
+                renderable.render(&mut render_pass, scene);
+            }
+        });
+
+        self.postprocess.add_passes(
+            &mut graph,
+            &self.gpu,
+            hdr,
+            backbuffer,
+            width,
+            height,
+        );
 
-            
+        if let Err(e) = graph.execute(&self.gpu.device, &mut self.transients, &mut encoder) {
+            log::error!("{:?}", e);
         }
 
         self.gpu.queue.submit(std::iter::once(encoder.finish()));
-        Ok(())
     }
 
-    fn create_vertex_buffer(&self, data: &[u8]) {
-        let buffer = self.gpu.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: data,
-            usage: wgpu::BufferUsages::VERTEX,
-        });
+    pub fn create_texture(&mut self, bytes: &[u8], label: &str) -> anyhow::Result<Handle<Texture>> {
+        let texture = Texture::from_bytes(&self.gpu.device, &self.gpu.queue, bytes, label)?;
+        Ok(self.assets.add_texture(label, texture))
     }
 
-    fn create_index_buffer(&self, data: &[u8]) {
-        let buffer = self.gpu.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Index Buffer"),
-            contents: data,
-            usage: wgpu::BufferUsages::INDEX,
-        });
+    // Makes the layout of `B` available to renderables and keeps it across
+    // device losses.
+    pub fn register_bind_group<B: BindGroup>(&mut self) -> Result<(), GpuError> {
+        self.bindgroups.register::<B>(&self.gpu)
+    }
+
+    pub fn bind_groups(&self) -> &BindGroups {
+        &self.bindgroups
+    }
+
+    pub fn create_model(&self, name: &str, vertices: &[ModelVertex], indices: &[u32], texture: Handle<Texture>) -> anyhow::Result<Model> {
+        Ok(Model::from_mesh(name, vertices, indices, texture, &self.gpu, &self.bindgroups)?)
     }
 
-    fn create_new_model(&self, filename : &str) {
-        let model = model::Model::new(filename, &self.gpu);
+    // Starts loading in the background, see `AssetServer`.
+    pub fn load_texture(&mut self, filename: &str) -> Handle<Texture> {
+        self.assets.load_texture(filename)
     }
-    
-}
\ No newline at end of file
+
+    pub fn load_model(&mut self, filename: &str) -> Handle<Model> {
+        self.assets.load_model(filename)
+    }
+
+    pub fn load_shader(&mut self, filename: &str) -> anyhow::Result<Handle<Shader>> {
+        self.assets.load_shader(filename, &self.gpu)
+    }
+
+    // Uploads assets that finished loading or were reloaded and lets
+    // renderables pick them up.
+    pub fn update_assets(&mut self) {
+        self.assets.update(&self.gpu, &self.bindgroups);
+        self.scene.refresh(&self.gpu, &self.bindgroups);
+        if let Err(e) = self.postprocess.refresh_shader(&self.gpu) {
+            log::error!("failed to rebuild the post-processing pipelines: {:?}", e);
+        }
+        for renderable in &mut self.renderables {
+            renderable.prepare();
+            for features in renderable.material_features() {
+                self.scene.request(features, &mut self.assets, &self.gpu, &self.bindgroups);
+            }
+        }
+    }
+}
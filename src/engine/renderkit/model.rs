@@ -1,16 +1,21 @@
 
 use std::io::{BufReader, Cursor};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
 
-use wgpu::util::DeviceExt;
+use crate::engine::resource::{load_string, Handle, SharedBytes};
 
-use crate::engine::resource::load_string;
-
-use super::{texture::Texture, BindGroups, gpuhandle::GPUHandle, Renderable, buffers::modelvertex::ModelVertex};
+use super::meshfile::{Bounds, MeshFile};
+use super::permutations::{MaterialFeatures, ShaderPermutations};
+use super::{texture::Texture, bindgroups::{BindGroup, TextureBindGroup}, BindGroups, gpuerror::GpuError, gpuhandle::GPUHandle, Renderable, buffers::modelvertex::ModelVertex};
 
 
 pub struct Material {
     pub name : String,
-    pub diffuse_texture: Texture,
+    pub diffuse_texture: Handle<Texture>,
+    // Picks the permutation of the scene shader the material is drawn with.
+    pub features: MaterialFeatures,
     pub bind_group: wgpu::BindGroup,
 }
 
@@ -20,62 +25,91 @@ pub struct Mesh {
     pub index_buffer: wgpu::Buffer,
     pub index_count: u32,
     pub material: usize,
+    pub bounds: Bounds,
+    // Where the buffer contents are in the mesh file, for rebuilding them
+    // after a device loss.
+    source: SharedBytes,
+    vertex_range: Range<usize>,
+    index_range: Range<usize>,
 }
 
 pub struct Model {
     pub name: String,
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
+    pub bounds: Bounds,
 }
 
-impl Material { 
-    pub fn new(name: String, diffuse_texture: Texture, device: &wgpu::Device, bindgroups: &BindGroups) -> Self {
-        let texture_bind_group = bindgroups.texture.create_bind_group(&diffuse_texture, device);
+pub struct MaterialData {
+    pub name: String,
+    pub diffuse_texture: String,
+    pub features: MaterialFeatures,
+}
 
-        Material {
-            name,
-            diffuse_texture,
-            bind_group: texture_bind_group,
-        }
-    }
+pub struct MeshData {
+    pub name: String,
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+    pub material: usize,
 }
 
-impl Renderable for Model {
-    fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass) {
-        for mesh in &self.meshes {
-            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-            render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-            render_pass.draw_indexed(0..mesh.index_count, 0, 0..1);
-        }
-    }
+// An OBJ file parsed into CPU side data, which doesn't need the GPU and so
+// can be loaded off the main thread.
+pub struct ObjData {
+    pub meshes: Vec<MeshData>,
+    pub materials: Vec<MaterialData>,
 }
 
-impl Model {
-    pub async fn new(filename: &str, gpu: &GPUHandle, bindgroups: &BindGroups) -> anyhow::Result<Model> {
-        let obj_text = load_string(filename).await?;
-        let obj_cursor = Cursor::new(obj_text);
-        let mut obj_reader = BufReader::new(obj_cursor);
+impl ObjData {
+    pub fn load(filename: &str) -> anyhow::Result<ObjData> {
+        let dir = Path::new(filename).parent().unwrap_or(Path::new(""));
+        Self::from_obj(&load_string(filename)?, |path| load_string(&dir.join(path).to_string_lossy()))
+    }
+
+    // Parses OBJ source, calling `load_mtl` for the material libraries it
+    // references, whose paths are relative to the OBJ file.
+    pub fn from_obj(obj_text: &str, load_mtl: impl Fn(&Path) -> anyhow::Result<String>) -> anyhow::Result<ObjData> {
+        let mut obj_reader = BufReader::new(Cursor::new(obj_text));
 
-        let (models, obj_materials) = tobj::load_obj_buf_async(
-            &mut obj_reader, 
+        let (models, obj_materials) = tobj::load_obj_buf(
+            &mut obj_reader,
             &tobj::LoadOptions{
                 triangulate: true,
                 single_index: true,
                 ..Default::default()
-            }, |p| async move {
-                let mat_text = load_string(&p).await.unwrap();
+            }, |p| {
+                let mat_text = load_mtl(p).map_err(|_| tobj::LoadError::OpenFileFailed)?;
                 tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
-            }).await?;
-        
-        let mut materials = Vec::new();
-
-        for m in obj_materials? {
-            let diffuse_texture = Texture::load_texture(&m.diffuse_texture, &gpu).await?;
-            let material = Material::new(m.name, diffuse_texture, &gpu.device, &bindgroups);
-            materials.push(material);
-        }
+            })?;
+
+        let materials: Vec<_> = obj_materials?.into_iter()
+            .map(|m| {
+                let mut features = MaterialFeatures::NONE;
+                if !m.normal_texture.is_empty() {
+                    features |= MaterialFeatures::NORMAL_MAP;
+                }
+                if m.unknown_param.contains_key("map_Ke") {
+                    features |= MaterialFeatures::EMISSIVE;
+                }
+                if !m.dissolve_texture.is_empty() {
+                    features |= MaterialFeatures::ALPHA_TEST;
+                }
+                MaterialData {
+                    name: m.name,
+                    diffuse_texture: m.diffuse_texture,
+                    features,
+                }
+            })
+            .collect();
         let meshes = models.into_iter()
             .map(|m| {
+                // Mesh files can't refer to a material that doesn't exist.
+                let material = m.mesh.material_id.unwrap_or(0);
+                if material >= materials.len() {
+                    anyhow::bail!("mesh {} has no material", m.name);
+                }
+                // Missing texture coordinates and normals are defaulted the
+                // same way as for glTF.
                 let vertices = (0..m.mesh.positions.len() / 3)
                     .map(|i| ModelVertex {
                         position: [
@@ -83,35 +117,282 @@ impl Model {
                             m.mesh.positions[i * 3 + 1],
                             m.mesh.positions[i * 3 + 2],
                         ],
-                        tex_coords: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]],
-                        normal: [
-                            m.mesh.normals[i * 3],
-                            m.mesh.normals[i * 3 + 1],
-                            m.mesh.normals[i * 3 + 2],
-                        ],
+                        tex_coords: m.mesh.texcoords.get(i * 2..i * 2 + 2).map_or([0.0, 0.0], |uv| [uv[0], uv[1]]),
+                        normal: m.mesh.normals.get(i * 3..i * 3 + 3).map_or([0.0, 0.0, 1.0], |n| [n[0], n[1], n[2]]),
                     })
                     .collect::<Vec<_>>();
-                let vertex_buffer = ModelVertex::new_buffer(&gpu, &vertices);
-                let index_buffer = gpu.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Index Buffer"),
-                    contents: bytemuck::cast_slice(&m.mesh.indices),
-                    usage: wgpu::BufferUsages::INDEX,
-                });
-
-                Mesh {
+
+                Ok(MeshData {
                     name: m.name,
-                    vertex_buffer,
-                    index_buffer,
-                    index_count: m.mesh.indices.len() as u32,
-                    material: m.mesh.material_id.unwrap_or(0),
+                    vertices,
+                    indices: m.mesh.indices,
+                    material,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(ObjData { meshes, materials })
+    }
+}
+
+impl Material { 
+    pub fn new(name: String, diffuse_texture: Handle<Texture>, features: MaterialFeatures, gpu: &GPUHandle, bindgroups: &BindGroups) -> Result<Self, GpuError> {
+        let label = format!("material {} ({})", name, diffuse_texture.path());
+        let texture_bind_group = bindgroups.get::<TextureBindGroup>().create_bind_group(&diffuse_texture, &label, gpu)?;
+
+        Ok(Material {
+            name,
+            diffuse_texture,
+            features,
+            bind_group: texture_bind_group,
+        })
+    }
+}
+
+impl Mesh {
+    // Uploads straight from the mesh file's bytes, without copying them
+    // into vertices first. `model` is only used to label the buffers.
+    #[allow(clippy::too_many_arguments)]
+    fn new(model: &str, name: String, source: SharedBytes, vertex_range: Range<usize>, index_range: Range<usize>, material: usize, bounds: Bounds, gpu: &GPUHandle) -> Result<Self, GpuError> {
+        let data = (*source).as_ref();
+        let vertex_buffer = gpu.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} {} vertices", model, name)),
+            contents: &data[vertex_range.clone()],
+            usage: wgpu::BufferUsages::VERTEX,
+        })?;
+        let index_buffer = gpu.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} {} indices", model, name)),
+            contents: &data[index_range.clone()],
+            usage: wgpu::BufferUsages::INDEX,
+        })?;
+        Ok(Mesh {
+            name,
+            vertex_buffer,
+            index_buffer,
+            index_count: (index_range.len() / 4) as u32,
+            material,
+            bounds,
+            source,
+            vertex_range,
+            index_range,
+        })
+    }
+
+    pub fn vertices(&self) -> Vec<ModelVertex> {
+        bytemuck::pod_collect_to_vec(&(*self.source).as_ref()[self.vertex_range.clone()])
+    }
+
+    pub fn indices(&self) -> Vec<u32> {
+        bytemuck::pod_collect_to_vec(&(*self.source).as_ref()[self.index_range.clone()])
+    }
+}
+
+impl Renderable for Model {
+    fn material_features(&self) -> Vec<MaterialFeatures> {
+        let mut features = self.materials.iter().map(|material| material.features).collect::<Vec<_>>();
+        features.sort();
+        features.dedup();
+        features
+    }
+
+    fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, scene: &'a ShaderPermutations) {
+        let mut current = MaterialFeatures::NONE;
+        for mesh in &self.meshes {
+            if let Some(material) = self.materials.get(mesh.material) {
+                // Permutations that failed to compile fall back to the plain one.
+                if material.features != current {
+                    if let Some(pipeline) = scene.get(material.features).or_else(|| scene.get(MaterialFeatures::NONE)) {
+                        render_pass.set_pipeline(&pipeline.pipeline);
+                    }
+                    current = material.features;
                 }
+                render_pass.set_bind_group(0, &material.bind_group, &[]);
+            }
+            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed(0..mesh.index_count, 0, 0..1);
+        }
+    }
+
+    fn restore(&mut self, gpu: &GPUHandle, bindgroups: &BindGroups) -> anyhow::Result<()> {
+        *self = self.rebuild(gpu, bindgroups)?;
+        Ok(())
+    }
+}
+
+// Models loaded through the `AssetServer` are restored by the server itself.
+// Until a model has loaded the handle points at an empty placeholder.
+impl Renderable for Handle<Model> {
+    fn prepare(&mut self) {
+        self.refresh();
+    }
+
+    fn material_features(&self) -> Vec<MaterialFeatures> {
+        (**self).material_features()
+    }
+
+    fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, scene: &'a ShaderPermutations) {
+        (**self).render(render_pass, scene);
+    }
+
+    fn restore(&mut self, _gpu: &GPUHandle, _bindgroups: &BindGroups) -> anyhow::Result<()> {
+        self.refresh();
+        Ok(())
+    }
+}
+
+impl Model {
+    pub fn from_mesh(
+        name: &str,
+        vertices: &[ModelVertex],
+        indices: &[u32],
+        diffuse_texture: Handle<Texture>,
+        gpu: &GPUHandle,
+        bindgroups: &BindGroups,
+    ) -> Result<Model, GpuError> {
+        let obj = ObjData {
+            meshes: vec![MeshData {
+                name: name.to_string(),
+                vertices: vertices.to_vec(),
+                indices: indices.to_vec(),
+                material: 0,
+            }],
+            materials: vec![MaterialData {
+                name: name.to_string(),
+                diffuse_texture: diffuse_texture.path().to_string(),
+                features: MaterialFeatures::NONE,
+            }],
+        };
+        Self::from_mesh_file(name, MeshFile::from_obj(&obj), vec![diffuse_texture], gpu, bindgroups)
+    }
+
+    // A model with nothing to draw, used while the real one is loading.
+    pub fn empty(name: &str) -> Model {
+        Model {
+            name: name.to_string(),
+            meshes: Vec::new(),
+            materials: Vec::new(),
+            bounds: Bounds::EMPTY,
+        }
+    }
+
+    // Uploads a parsed OBJ file. `textures` holds the diffuse texture of each
+    // of `obj.materials`, in order.
+    pub fn from_obj(name: &str, obj: ObjData, textures: Vec<Handle<Texture>>, gpu: &GPUHandle, bindgroups: &BindGroups) -> Result<Model, GpuError> {
+        Self::from_mesh_file(name, MeshFile::from_obj(&obj), textures, gpu, bindgroups)
+    }
+
+    // Uploads a mesh file. `textures` holds the diffuse texture of each of
+    // `mesh.materials`, in order.
+    pub fn from_mesh_file(name: &str, mesh: MeshFile, textures: Vec<Handle<Texture>>, gpu: &GPUHandle, bindgroups: &BindGroups) -> Result<Model, GpuError> {
+        let materials = mesh.materials.into_iter()
+            .zip(textures)
+            .map(|(material, texture)| Material::new(material.name, texture, material.features, gpu, bindgroups))
+            .collect::<Result<_, _>>()?;
+        let meshes = mesh.submeshes.into_iter()
+            .map(|submesh| Mesh::new(
+                name,
+                submesh.name,
+                mesh.bytes.clone(),
+                submesh.vertex_range,
+                submesh.index_range,
+                submesh.material,
+                submesh.bounds,
+                gpu,
+            ))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Model {
+            name: name.to_string(),
+            meshes,
+            materials,
+            bounds: mesh.bounds,
+        })
+    }
+
+    // The model as a mesh file, with its textures referenced by asset path.
+    pub fn to_mesh_file(&self) -> MeshFile {
+        let obj = ObjData {
+            meshes: self.meshes.iter()
+                .map(|mesh| MeshData {
+                    name: mesh.name.clone(),
+                    vertices: mesh.vertices(),
+                    indices: mesh.indices(),
+                    material: mesh.material,
+                })
+                .collect(),
+            materials: self.materials.iter()
+                .map(|material| MaterialData {
+                    name: material.name.clone(),
+                    diffuse_texture: material.diffuse_texture.path().to_string(),
+                    features: material.features,
+                })
+                .collect(),
+        };
+        MeshFile::from_obj(&obj)
+    }
+
+    // A copy of the model with all GPU resources created on `gpu`, e.g. after
+    // the device was lost. Textures are expected to be restored already.
+    pub fn rebuild(&self, gpu: &GPUHandle, bindgroups: &BindGroups) -> anyhow::Result<Model> {
+        let materials = self.materials.iter()
+            .map(|material| {
+                let mut diffuse_texture = material.diffuse_texture.clone();
+                diffuse_texture.refresh();
+                Material::new(material.name.clone(), diffuse_texture, material.features, gpu, bindgroups)
             })
-            .collect::<Vec<_>>();
+            .collect::<Result<_, _>>()?;
+        let meshes = self.meshes.iter()
+            .map(|mesh| Mesh::new(
+                &self.name,
+                mesh.name.clone(),
+                Arc::clone(&mesh.source),
+                mesh.vertex_range.clone(),
+                mesh.index_range.clone(),
+                mesh.material,
+                mesh.bounds,
+                gpu,
+            ))
+            .collect::<Result<_, _>>()?;
 
         Ok(Model {
-            name: filename.to_string(),
+            name: self.name.clone(),
             meshes,
             materials,
+            bounds: self.bounds,
         })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MTL: &str = "newmtl plain\nmap_Kd plain.png\n";
+
+    fn parse(obj: &str) -> anyhow::Result<ObjData> {
+        ObjData::from_obj(obj, |_| Ok(MTL.to_string()))
+    }
+
+    #[test]
+    fn missing_tex_coords_and_normals_are_defaulted() {
+        let obj = parse("mtllib plain.mtl\nusemtl plain\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        let vertices = &obj.meshes[0].vertices;
+        assert_eq!(vertices.len(), 3);
+        assert_eq!(vertices[1].position, [1.0, 0.0, 0.0]);
+        assert!(vertices.iter().all(|vertex| vertex.tex_coords == [0.0, 0.0] && vertex.normal == [0.0, 0.0, 1.0]));
+    }
+
+    #[test]
+    fn tex_coords_and_normals_are_read() {
+        let obj = parse("mtllib plain.mtl\nusemtl plain\nv 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0.5 0.25\nvn 0 1 0\nf 1/1/1 2/1/1 3/1/1\n").unwrap();
+        let vertex = obj.meshes[0].vertices[0];
+        assert_eq!((vertex.tex_coords, vertex.normal), ([0.5, 0.25], [0.0, 1.0, 0.0]));
+        assert_eq!(obj.materials[0].diffuse_texture, "plain.png");
+    }
+
+    #[test]
+    fn meshes_without_a_material_are_rejected() {
+        assert!(parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").is_err());
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::{BitOr, BitOrAssign};
+use std::str::FromStr;
+
+use anyhow::bail;
+
+use crate::engine::resource::{load_string, AssetServer, Handle};
+
+use super::{bindgroups::BindGroups, gpuhandle::GPUHandle, pipelinehandle::PipelineHandle, preprocessor::ShaderSource, shader::Shader};
+
+// Optional parts of a material. Every feature a material has is defined for
+// its shader, e.g. `#ifdef ALPHA_TEST`, so materials without it don't pay
+// for it. Features a shader never tests don't change it and are dropped
+// before a permutation is picked.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MaterialFeatures(u32);
+
+impl MaterialFeatures {
+    pub const NONE: MaterialFeatures = MaterialFeatures(0);
+    pub const NORMAL_MAP: MaterialFeatures = MaterialFeatures(1);
+    pub const EMISSIVE: MaterialFeatures = MaterialFeatures(2);
+    pub const ALPHA_TEST: MaterialFeatures = MaterialFeatures(4);
+    pub const SKINNING: MaterialFeatures = MaterialFeatures(8);
+
+    // Every feature with the name it's defined as in shaders.
+    const NAMES: [(MaterialFeatures, &'static str); 4] = [
+        (MaterialFeatures::NORMAL_MAP, "NORMAL_MAP"),
+        (MaterialFeatures::EMISSIVE, "EMISSIVE"),
+        (MaterialFeatures::ALPHA_TEST, "ALPHA_TEST"),
+        (MaterialFeatures::SKINNING, "SKINNING"),
+    ];
+
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    // None if `bits` has a bit that isn't a feature.
+    pub fn from_bits(bits: u32) -> Option<MaterialFeatures> {
+        let all = Self::NAMES.iter().fold(0, |all, (feature, _)| all | feature.0);
+        (bits & !all == 0).then_some(MaterialFeatures(bits))
+    }
+
+    pub fn contains(self, other: MaterialFeatures) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    // Only the features whose names are in `tested`.
+    pub fn tested_in(self, tested: &HashSet<String>) -> MaterialFeatures {
+        let kept = Self::NAMES.iter()
+            .filter(|(feature, name)| self.contains(*feature) && tested.contains(*name))
+            .fold(0, |kept, (feature, _)| kept | feature.0);
+        MaterialFeatures(kept)
+    }
+
+    pub fn defines(self) -> Vec<(&'static str, &'static str)> {
+        Self::NAMES.iter()
+            .filter(|(feature, _)| self.contains(*feature))
+            .map(|&(_, name)| (name, ""))
+            .collect()
+    }
+}
+
+impl BitOr for MaterialFeatures {
+    type Output = MaterialFeatures;
+
+    fn bitor(self, other: MaterialFeatures) -> MaterialFeatures {
+        MaterialFeatures(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for MaterialFeatures {
+    fn bitor_assign(&mut self, other: MaterialFeatures) {
+        self.0 |= other.0;
+    }
+}
+
+// Written as e.g. `normal_map+alpha_test`, or `none`.
+impl fmt::Display for MaterialFeatures {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = Self::NAMES.iter()
+            .filter(|(feature, _)| self.contains(*feature))
+            .map(|(_, name)| name.to_lowercase())
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", names.join("+"))
+        }
+    }
+}
+
+impl FromStr for MaterialFeatures {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> anyhow::Result<MaterialFeatures> {
+        let mut features = MaterialFeatures::NONE;
+        for name in text.split('+').map(str::trim) {
+            if name.eq_ignore_ascii_case("none") {
+                continue;
+            }
+            match Self::NAMES.iter().find(|(_, known)| known.eq_ignore_ascii_case(name)) {
+                Some(&(feature, _)) => features |= feature,
+                None => bail!("unknown material feature {:?}", name),
+            }
+        }
+        Ok(features)
+    }
+}
+
+// Reads a list of permutations to compile up front, one per line as
+// `MaterialFeatures` are displayed. Empty lines and `#` comments are skipped.
+pub fn parse_permutation_list(text: &str) -> anyhow::Result<Vec<MaterialFeatures>> {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index, line.split('#').next().unwrap_or("").trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(index, line)| line.parse().map_err(|e| anyhow::anyhow!("line {}: {}", index + 1, e)))
+        .collect()
+}
+
+// Creates the pipeline for one permutation of the shader, with the sample
+// count of the targets it draws into.
+pub type BuildPipeline = fn(&GPUHandle, &BindGroups, &Shader, u32) -> anyhow::Result<PipelineHandle>;
+
+struct Permutation {
+    shader: Handle<Shader>,
+    pipeline: PipelineHandle,
+}
+
+// The permutations of one shader, compiled the first time a material needs
+// them and cached along with their pipeline. Compiling takes long enough to
+// cause a hitch, so the ones known to be needed can be compiled up front
+// with `prewarm`.
+pub struct ShaderPermutations {
+    path: String,
+    build: BuildPipeline,
+    sample_count: u32,
+    permutations: HashMap<MaterialFeatures, Permutation>,
+    // The permutation requested features map to, without the ones the shader
+    // doesn't test, so materials that only differ in those share a pipeline.
+    resolved: HashMap<MaterialFeatures, MaterialFeatures>,
+    // Permutations that failed to compile, not tried again until the shader
+    // changes.
+    failed: HashSet<MaterialFeatures>,
+}
+
+impl ShaderPermutations {
+    pub fn new(path: &str, build: BuildPipeline, sample_count: u32) -> Self {
+        ShaderPermutations {
+            path: path.to_string(),
+            build,
+            sample_count,
+            permutations: HashMap::new(),
+            resolved: HashMap::new(),
+            failed: HashSet::new(),
+        }
+    }
+
+    // Compiles the permutation for `features` unless it's cached already.
+    pub fn prepare(&mut self, features: MaterialFeatures, assets: &mut AssetServer, gpu: &GPUHandle, bindgroups: &BindGroups) -> anyhow::Result<&PipelineHandle> {
+        let key = match self.resolved.get(&features) {
+            Some(&key) => key,
+            None => {
+                // Only preprocessed, so it's cheap next to compiling.
+                let source = ShaderSource::load(&self.path, &features.defines())?;
+                let key = features.tested_in(&source.tested);
+                self.resolved.insert(features, key);
+                key
+            }
+        };
+        if !self.permutations.contains_key(&key) {
+            let shader = assets.load_shader_with(&self.path, &key.defines(), gpu)?;
+            let pipeline = (self.build)(gpu, bindgroups, &shader, self.sample_count)?;
+            log::debug!("compiled {} for {}", self.path, key);
+            self.permutations.insert(key, Permutation { shader, pipeline });
+        }
+        Ok(&self.permutations[&key].pipeline)
+    }
+
+    // Like `prepare`, for use every frame: a permutation that failed is
+    // logged once and not tried again until the shader is reloaded.
+    pub fn request(&mut self, features: MaterialFeatures, assets: &mut AssetServer, gpu: &GPUHandle, bindgroups: &BindGroups) {
+        if self.failed.contains(&features) {
+            return;
+        }
+        if let Err(e) = self.prepare(features, assets, gpu, bindgroups) {
+            log::error!("failed to compile {} for {}: {:?}", self.path, features, e);
+            self.failed.insert(features);
+        }
+    }
+
+    pub fn prewarm(&mut self, list: &[MaterialFeatures], assets: &mut AssetServer, gpu: &GPUHandle, bindgroups: &BindGroups) -> anyhow::Result<()> {
+        for &features in list {
+            self.prepare(features, assets, gpu, bindgroups)?;
+        }
+        Ok(())
+    }
+
+    // Prewarms the permutations listed in the asset `path`, see
+    // `parse_permutation_list`.
+    pub fn prewarm_from_list(&mut self, path: &str, assets: &mut AssetServer, gpu: &GPUHandle, bindgroups: &BindGroups) -> anyhow::Result<()> {
+        let list = parse_permutation_list(&load_string(path)?).map_err(|e| e.context(format!("in {}", path)))?;
+        self.prewarm(&list, assets, gpu, bindgroups)
+    }
+
+    // The cached pipeline for `features`, if it was prepared.
+    pub fn get(&self, features: MaterialFeatures) -> Option<&PipelineHandle> {
+        let key = self.resolved.get(&features)?;
+        self.permutations.get(key).map(|permutation| &permutation.pipeline)
+    }
+
+    // Every permutation prepared so far.
+    pub fn prepared(&self) -> Vec<MaterialFeatures> {
+        let mut list = self.permutations.keys().copied().collect::<Vec<_>>();
+        list.sort();
+        list
+    }
+
+    // Rebuilds the pipelines of permutations whose shader was reloaded. A
+    // pipeline that fails to build is kept as it was.
+    pub fn refresh(&mut self, gpu: &GPUHandle, bindgroups: &BindGroups) -> bool {
+        let mut changed = false;
+        for (features, permutation) in &mut self.permutations {
+            if !permutation.shader.refresh() {
+                continue;
+            }
+            changed = true;
+            match (self.build)(gpu, bindgroups, &permutation.shader, self.sample_count) {
+                Ok(pipeline) => permutation.pipeline = pipeline,
+                Err(e) => log::error!("failed to rebuild {} for {}, keeping the previous one: {:?}", self.path, features, e),
+            }
+        }
+        if changed {
+            // The reloaded shader may test other features, so features are
+            // resolved again unless they map to themselves.
+            self.resolved.retain(|features, key| features == key);
+            self.failed.clear();
+        }
+        changed
+    }
+
+    // Rebuilds every cached pipeline, e.g. for a new sample count or after
+    // the device was lost. Nothing changes if one of them fails, including
+    // which version of the shader each permutation has seen.
+    pub fn rebuild(&mut self, sample_count: u32, gpu: &GPUHandle, bindgroups: &BindGroups) -> anyhow::Result<()> {
+        let mut rebuilt = Vec::new();
+        let mut reloaded = false;
+        for (&features, permutation) in &self.permutations {
+            // Built from a refreshed copy, so a failure leaves the reload
+            // for `refresh` to pick up.
+            let mut shader = permutation.shader.clone();
+            reloaded |= shader.refresh();
+            let pipeline = (self.build)(gpu, bindgroups, &shader, sample_count)?;
+            rebuilt.push((features, Permutation { shader, pipeline }));
+        }
+        self.permutations.extend(rebuilt);
+        if reloaded {
+            self.resolved.retain(|features, key| features == key);
+        }
+        self.sample_count = sample_count;
+        self.failed.clear();
+        Ok(())
+    }
+}
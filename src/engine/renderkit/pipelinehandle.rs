@@ -1,26 +1,34 @@
 use wgpu::{BindGroupLayout, DepthStencilState, FragmentState, VertexState};
 
+use super::gpuerror::{self, GpuError};
+
 pub struct PipelineHandle {
     pub pipeline: wgpu::RenderPipeline,
     pub pipeline_layout: wgpu::PipelineLayout,
+    pub sample_count: u32,
 }
 
 impl PipelineHandle {
     pub fn new(
+        label: &str,
         bind_group_layouts: &[&BindGroupLayout],
         vertex: VertexState,
         fragment: Option<FragmentState>,
         depth_stencil: Option<DepthStencilState>,
+        sample_count: u32,
         device: &wgpu::Device,
-    ) -> Self {
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("pipeline_layout"),
-            bind_group_layouts,
-            push_constant_ranges: &[],
-        });
+    ) -> Result<Self, GpuError> {
+        let layout_label = format!("{}_layout", label);
+        let pipeline_layout = gpuerror::scoped(device, "pipeline layout", Some(&layout_label), || {
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(&layout_label),
+                bind_group_layouts,
+                push_constant_ranges: &[],
+            })
+        })?;
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("render_pipeline"),
+        let pipeline = gpuerror::scoped(device, "render pipeline", Some(label), || device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
             layout: Some(&pipeline_layout),
             vertex,
             fragment,
@@ -35,16 +43,17 @@ impl PipelineHandle {
                 conservative: false,
             },
             multisample: wgpu::MultisampleState {
-                count: 1,
+                count: sample_count,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
             multiview: None,
-        });
-        Self{
+        }))?;
+        Ok(Self{
             pipeline,
             pipeline_layout,
-        }
+            sample_count,
+        })
     }
 
 
@@ -0,0 +1,514 @@
+use std::cell::{Ref, RefCell};
+use std::collections::{hash_map::Entry, HashMap};
+
+use wgpu::util::DeviceExt;
+
+use crate::engine::resource::Handle;
+
+use super::{
+    gpuerror::GpuError,
+    gpuhandle::GPUHandle,
+    pipelinehandle::PipelineHandle,
+    reflection::ShaderLayout,
+    rendergraph::{PassResources, RenderGraph, ResourceId, TextureDesc},
+    shader::Shader,
+};
+
+// Loaded through the asset system, see `RenderKit::new`.
+pub const SHADER: &str = "shaders/postprocess.wgsl";
+
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+const BLOOM_MIPS: u32 = 5;
+const IDENTITY_LUT_SIZE: u32 = 16;
+
+const FLAG_BLOOM: u32 = 1;
+const FLAG_COLOR_GRADING: u32 = 2;
+const FLAG_VIGNETTE: u32 = 4;
+
+// The entry points drawn with each bind group layout.
+const SOURCE_ENTRY_POINTS: [&str; 5] = ["vs_fullscreen", "fs_bloom_prefilter", "fs_bloom_downsample", "fs_bloom_upsample", "fs_fxaa"];
+const COMPOSITE_ENTRY_POINTS: [&str; 2] = ["vs_fullscreen", "fs_composite"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tonemapper {
+    None,
+    Reinhard,
+    Aces,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PostProcessSettings {
+    pub exposure: f32,
+    pub tonemapper: Tonemapper,
+    pub bloom: bool,
+    pub bloom_threshold: f32,
+    pub bloom_intensity: f32,
+    pub fxaa: bool,
+    pub vignette: bool,
+    pub vignette_intensity: f32,
+    pub color_grading: bool,
+}
+
+impl Default for PostProcessSettings {
+    fn default() -> Self {
+        PostProcessSettings {
+            exposure: 1.0,
+            tonemapper: Tonemapper::Aces,
+            bloom: true,
+            bloom_threshold: 1.0,
+            bloom_intensity: 0.05,
+            fxaa: true,
+            vignette: false,
+            vignette_intensity: 0.5,
+            color_grading: false,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostSettingsUniform {
+    exposure: f32,
+    bloom_threshold: f32,
+    bloom_intensity: f32,
+    vignette_intensity: f32,
+    tonemapper: u32,
+    flags: u32,
+    lut_size: f32,
+    _padding: f32,
+}
+
+// Which bind group a pass uses, by the addresses of the views it reads.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum BindGroupKey {
+    Source(usize),
+    Composite { source: usize, bloom: usize },
+}
+
+// Bind groups of the passes, made from render graph transients and kept until
+// the graph reallocates them, e.g. on resize.
+#[derive(Default)]
+struct BindGroupCache {
+    generation: u64,
+    bind_groups: HashMap<BindGroupKey, wgpu::BindGroup>,
+}
+
+// The scene renders into an HDR target which this chain resolves into the
+// swapchain: bloom -> composite (exposure, tonemapping, grading, vignette) -> FXAA.
+pub struct PostProcess {
+    pub settings: PostProcessSettings,
+    settings_buffer: wgpu::Buffer,
+    source_layout: wgpu::BindGroupLayout,
+    composite_layout: wgpu::BindGroupLayout,
+    // What the shader declares for each layout, to create bind groups by name.
+    source_bindings: ShaderLayout,
+    composite_bindings: ShaderLayout,
+    sampler: wgpu::Sampler,
+    bloom_prefilter: PipelineHandle,
+    bloom_downsample: PipelineHandle,
+    bloom_upsample: PipelineHandle,
+    composite: PipelineHandle,
+    fxaa: PipelineHandle,
+    black: wgpu::TextureView,
+    lut: wgpu::TextureView,
+    lut_size: u32,
+    // Texels of a custom LUT, kept so it survives a device loss.
+    lut_data: Option<Vec<u8>>,
+    output_format: wgpu::TextureFormat,
+    shader: Handle<Shader>,
+    // Filled while the graph executes, which only borrows `self`.
+    bind_groups: RefCell<BindGroupCache>,
+}
+
+impl PostProcess {
+    pub fn new(gpu: &GPUHandle, output_format: wgpu::TextureFormat, shader: Handle<Shader>) -> anyhow::Result<Self> {
+        let (device, queue) = (&gpu.device, &gpu.queue);
+        let settings = PostProcessSettings::default();
+
+        let settings_buffer = gpu.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("post_settings_buffer"),
+            size: std::mem::size_of::<PostSettingsUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })?;
+
+        // The layouts come from the shader, so they only need to change with it.
+        let source_bindings = shader.layout().for_entry_points(&SOURCE_ENTRY_POINTS);
+        source_bindings.require(0, &["t_source", "s_source", "settings"])?;
+        let composite_bindings = shader.layout().for_entry_points(&COMPOSITE_ENTRY_POINTS);
+        composite_bindings.require(0, &["t_source", "s_source", "settings", "t_bloom", "t_lut"])?;
+        let source_layout = source_bindings.create_layout(0, gpu, "post_source_bind_group_layout")?;
+        let composite_layout = composite_bindings.create_layout(0, gpu, "post_composite_bind_group_layout")?;
+
+        let sampler = gpu.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("post_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        })?;
+
+        let pipeline = |layout: &wgpu::BindGroupLayout, entry_point: &str, format, blend| {
+            PipelineHandle::new(
+                entry_point,
+                &[layout],
+                wgpu::VertexState {
+                    module: &shader.module,
+                    entry_point: "vs_fullscreen",
+                    buffers: &[],
+                },
+                Some(wgpu::FragmentState {
+                    module: &shader.module,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                None,
+                1,
+                device,
+            )
+        };
+
+        let additive = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent::OVER,
+        };
+
+        let bloom_prefilter = pipeline(&source_layout, "fs_bloom_prefilter", HDR_FORMAT, None)?;
+        let bloom_downsample = pipeline(&source_layout, "fs_bloom_downsample", HDR_FORMAT, None)?;
+        let bloom_upsample = pipeline(&source_layout, "fs_bloom_upsample", HDR_FORMAT, Some(additive))?;
+        let composite = pipeline(&composite_layout, "fs_composite", output_format, None)?;
+        let fxaa = pipeline(&source_layout, "fs_fxaa", output_format, None)?;
+
+        let black = create_pixel_texture(device, queue, HDR_FORMAT, "post_black_texture", &[0; 8]);
+        let lut = create_identity_lut(device, queue, IDENTITY_LUT_SIZE);
+
+        Ok(PostProcess {
+            settings,
+            settings_buffer,
+            source_layout,
+            composite_layout,
+            source_bindings,
+            composite_bindings,
+            sampler,
+            bloom_prefilter,
+            bloom_downsample,
+            bloom_upsample,
+            composite,
+            fxaa,
+            black,
+            lut,
+            lut_size: IDENTITY_LUT_SIZE,
+            lut_data: None,
+            output_format,
+            shader,
+            bind_groups: RefCell::default(),
+        })
+    }
+
+    // Replaces the color grading LUT with one stored as a horizontal strip of
+    // `size` slices, each `size` x `size` pixels (blue selects the slice).
+    pub fn set_lut(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, img: &image::DynamicImage) -> anyhow::Result<()> {
+        let strip = img.to_rgba8();
+        let size = strip.height();
+        if size == 0 || strip.width() != size * size {
+            anyhow::bail!("LUT strip must be {}x{} pixels, got {}x{}", size * size, size, strip.width(), strip.height());
+        }
+
+        let mut data = Vec::with_capacity((size * size * size * 4) as usize);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    data.extend_from_slice(&strip.get_pixel(b * size + r, g).0);
+                }
+            }
+        }
+
+        self.lut = create_texture_3d(device, queue, size, &data);
+        self.bind_groups.get_mut().bind_groups.clear();
+        self.lut_size = size;
+        self.lut_data = Some(data);
+        Ok(())
+    }
+
+    // Recreates every GPU object on a new device, keeping the settings and LUT.
+    pub fn restore(&mut self, gpu: &GPUHandle, output_format: wgpu::TextureFormat) -> anyhow::Result<()> {
+        self.shader.refresh();
+        let mut restored = PostProcess::new(gpu, output_format, self.shader.clone())?;
+        restored.settings = self.settings;
+        if let Some(data) = self.lut_data.take() {
+            restored.lut = create_texture_3d(&gpu.device, &gpu.queue, self.lut_size, &data);
+            restored.lut_size = self.lut_size;
+            restored.lut_data = Some(data);
+        }
+        *self = restored;
+        Ok(())
+    }
+
+    // Rebuilds the pipelines if the shader was reloaded.
+    pub fn refresh_shader(&mut self, gpu: &GPUHandle) -> anyhow::Result<()> {
+        if self.shader.refresh() {
+            self.restore(gpu, self.output_format)?;
+        }
+        Ok(())
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue) {
+        let settings = &self.settings;
+        let mut flags = 0;
+        if settings.bloom {
+            flags |= FLAG_BLOOM;
+        }
+        if settings.color_grading {
+            flags |= FLAG_COLOR_GRADING;
+        }
+        if settings.vignette {
+            flags |= FLAG_VIGNETTE;
+        }
+
+        let uniform = PostSettingsUniform {
+            exposure: settings.exposure,
+            bloom_threshold: settings.bloom_threshold,
+            bloom_intensity: settings.bloom_intensity,
+            vignette_intensity: settings.vignette_intensity,
+            tonemapper: match settings.tonemapper {
+                Tonemapper::None => 0,
+                Tonemapper::Reinhard => 1,
+                Tonemapper::Aces => 2,
+            },
+            flags,
+            lut_size: self.lut_size as f32,
+            _padding: 0.0,
+        };
+        queue.write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // Adds the enabled effects to `graph`, reading the HDR scene from `hdr`
+    // and writing the final image to `output`.
+    pub fn add_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        gpu: &'a GPUHandle,
+        hdr: ResourceId,
+        output: ResourceId,
+        width: u32,
+        height: u32,
+    ) {
+        let bloom = if self.settings.bloom {
+            Some(self.add_bloom_passes(graph, gpu, hdr, width, height))
+        } else {
+            None
+        };
+
+        let composite_target = if self.settings.fxaa {
+            graph.create_texture("ldr", TextureDesc {
+                width,
+                height,
+                format: self.output_format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                sample_count: 1,
+            })
+        } else {
+            output
+        };
+
+        let mut reads = vec![hdr];
+        reads.extend(bloom);
+        graph.add_pass("composite", &reads, &[composite_target], move |resources, encoder| {
+            let bloom_view = match bloom {
+                Some(bloom) => resources.texture(bloom),
+                None => &self.black,
+            };
+            let source = resources.texture(hdr);
+            let key = BindGroupKey::Composite { source: address(source), bloom: address(bloom_view) };
+            let bind_group = self.cached_bind_group(resources, key, || {
+                self.composite_bindings.bind_group(0, &self.composite_layout, "post_composite_bind_group")
+                    .texture("t_source", source)
+                    .sampler("s_source", &self.sampler)
+                    .buffer("settings", &self.settings_buffer)
+                    .texture("t_bloom", bloom_view)
+                    .texture("t_lut", &self.lut)
+                    .build(gpu)
+            });
+            self.draw(encoder, &self.composite, bind_group, resources.texture(composite_target), true);
+        });
+
+        if self.settings.fxaa {
+            graph.add_pass("fxaa", &[composite_target], &[output], move |resources, encoder| {
+                let bind_group = self.source_bind_group(gpu, resources, composite_target);
+                self.draw(encoder, &self.fxaa, bind_group, resources.texture(output), true);
+            });
+        }
+    }
+
+    // Thresholds the scene into half resolution, blurs it down a mip chain and
+    // then additively upsamples back, returning the half resolution result.
+    fn add_bloom_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        gpu: &'a GPUHandle,
+        hdr: ResourceId,
+        width: u32,
+        height: u32,
+    ) -> ResourceId {
+        let mips: Vec<ResourceId> = (0..BLOOM_MIPS)
+            .map(|i| {
+                graph.create_texture(&format!("bloom_mip{}", i), TextureDesc {
+                    width: (width >> (i + 1)).max(1),
+                    height: (height >> (i + 1)).max(1),
+                    format: HDR_FORMAT,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                    sample_count: 1,
+                })
+            })
+            .collect();
+
+        let first = mips[0];
+        graph.add_pass("bloom_prefilter", &[hdr], &[first], move |resources, encoder| {
+            let bind_group = self.source_bind_group(gpu, resources, hdr);
+            self.draw(encoder, &self.bloom_prefilter, bind_group, resources.texture(first), true);
+        });
+
+        for i in 1..mips.len() {
+            let (source, target) = (mips[i - 1], mips[i]);
+            graph.add_pass(&format!("bloom_downsample{}", i), &[source], &[target], move |resources, encoder| {
+                let bind_group = self.source_bind_group(gpu, resources, source);
+                self.draw(encoder, &self.bloom_downsample, bind_group, resources.texture(target), true);
+            });
+        }
+
+        for i in (0..mips.len() - 1).rev() {
+            let (source, target) = (mips[i + 1], mips[i]);
+            graph.add_pass(&format!("bloom_upsample{}", i), &[source], &[target], move |resources, encoder| {
+                let bind_group = self.source_bind_group(gpu, resources, source);
+                self.draw(encoder, &self.bloom_upsample, bind_group, resources.texture(target), false);
+            });
+        }
+
+        first
+    }
+
+    fn source_bind_group<'r>(&'r self, gpu: &GPUHandle, resources: &PassResources, source: ResourceId) -> Result<Ref<'r, wgpu::BindGroup>, GpuError> {
+        let source = resources.texture(source);
+        self.cached_bind_group(resources, BindGroupKey::Source(address(source)), || {
+            self.source_bindings.bind_group(0, &self.source_layout, "post_source_bind_group")
+                .texture("t_source", source)
+                .sampler("s_source", &self.sampler)
+                .buffer("settings", &self.settings_buffer)
+                .build(gpu)
+        })
+    }
+
+    // The bind group for `key`, created with `create` unless it's cached. The
+    // cache is emptied whenever the graph's transients change, so a view's
+    // address can't be reused by another one while it's in there.
+    fn cached_bind_group(
+        &self,
+        resources: &PassResources,
+        key: BindGroupKey,
+        create: impl FnOnce() -> Result<wgpu::BindGroup, GpuError>,
+    ) -> Result<Ref<'_, wgpu::BindGroup>, GpuError> {
+        {
+            let mut cache = self.bind_groups.borrow_mut();
+            if cache.generation != resources.generation() {
+                cache.generation = resources.generation();
+                cache.bind_groups.clear();
+            }
+            if let Entry::Vacant(entry) = cache.bind_groups.entry(key) {
+                entry.insert(create()?);
+            }
+        }
+        Ok(Ref::map(self.bind_groups.borrow(), |cache| &cache.bind_groups[&key]))
+    }
+
+    fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &PipelineHandle,
+        bind_group: Result<Ref<wgpu::BindGroup>, GpuError>,
+        target: &wgpu::TextureView,
+        clear: bool,
+    ) {
+        // Skips the pass rather than letting wgpu panic on a bad bind group.
+        let bind_group = match bind_group {
+            Ok(bind_group) => bind_group,
+            Err(e) => {
+                log::error!("skipping a post process pass: {:?}", e);
+                return;
+            }
+        };
+        let load = if clear { wgpu::LoadOp::Clear(wgpu::Color::BLACK) } else { wgpu::LoadOp::Load };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Post Process Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations { load, store: true },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&pipeline.pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn address(view: &wgpu::TextureView) -> usize {
+    view as *const wgpu::TextureView as usize
+}
+
+fn create_pixel_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    format: wgpu::TextureFormat,
+    label: &str,
+    data: &[u8],
+) -> wgpu::TextureView {
+    let texture = device.create_texture_with_data(queue, &wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d { width: 1, height: 1, depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+    }, data);
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn create_texture_3d(device: &wgpu::Device, queue: &wgpu::Queue, size: u32, data: &[u8]) -> wgpu::TextureView {
+    let texture = device.create_texture_with_data(queue, &wgpu::TextureDescriptor {
+        label: Some("post_lut_texture"),
+        size: wgpu::Extent3d { width: size, height: size, depth_or_array_layers: size },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+    }, data);
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn create_identity_lut(device: &wgpu::Device, queue: &wgpu::Queue, size: u32) -> wgpu::TextureView {
+    let scale = |v: u32| (v * 255 / (size - 1)) as u8;
+    let mut data = Vec::with_capacity((size * size * size * 4) as usize);
+    for b in 0..size {
+        for g in 0..size {
+            for r in 0..size {
+                data.extend_from_slice(&[scale(r), scale(g), scale(b), 255]);
+            }
+        }
+    }
+    create_texture_3d(device, queue, size, &data)
+}
@@ -0,0 +1,334 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+use anyhow::bail;
+
+use crate::engine::resource::load_string;
+
+// Expands the directives below in WGSL source, each on a line of its own:
+//
+//   #include "path"          pastes another file, relative to the including
+//                            one. Files are only pasted the first time, so
+//                            snippets can include what they need.
+//   #define NAME [value]     defines NAME, whose uses are replaced by value
+//   #undef NAME
+//   #ifdef NAME, #ifndef NAME, #else, #endif
+//
+// The result remembers where each of its lines came from, so errors point at
+// the original files rather than the expanded source.
+pub struct ShaderSource {
+    pub code: String,
+    // Every file that went into `code`, the one it was loaded from first.
+    pub files: Vec<String>,
+    // The index into `files` and the line number of every line of `code`.
+    lines: Vec<(usize, u32)>,
+    // Every name tested by #ifdef or #ifndef, in kept branches or not.
+    pub tested: HashSet<String>,
+}
+
+// One level of #ifdef nesting.
+struct Condition {
+    // Whether the lines in the current branch are kept.
+    active: bool,
+    // Whether the enclosing branch is kept.
+    parent: bool,
+    seen_else: bool,
+    line: u32,
+}
+
+struct Expander<'a> {
+    read: &'a mut dyn FnMut(&str) -> anyhow::Result<String>,
+    defines: HashMap<String, String>,
+    included: HashSet<String>,
+    source: ShaderSource,
+}
+
+impl ShaderSource {
+    // Loads `path` and everything it includes through the asset system.
+    pub fn load(path: &str, defines: &[(&str, &str)]) -> anyhow::Result<ShaderSource> {
+        let source = load_string(path)?;
+        Self::preprocess(path, &source, defines, &mut |include| load_string(include))
+    }
+
+    // Expands `source`, which was loaded from `path`, calling `read` for every
+    // file it includes. `defines` are defined before the first line.
+    pub fn preprocess(
+        path: &str,
+        source: &str,
+        defines: &[(&str, &str)],
+        read: &mut dyn FnMut(&str) -> anyhow::Result<String>,
+    ) -> anyhow::Result<ShaderSource> {
+        let mut expander = Expander {
+            read,
+            defines: defines.iter().map(|&(name, value)| (name.to_string(), value.to_string())).collect(),
+            included: HashSet::new(),
+            source: ShaderSource {
+                code: String::new(),
+                files: Vec::new(),
+                lines: Vec::new(),
+                tested: HashSet::new(),
+            },
+        };
+        expander.included.insert(normalize(path));
+        expander.expand(&normalize(path), source)?;
+        Ok(expander.source)
+    }
+
+    // The file, line and column that `offset` into `code` came from, lines
+    // and columns starting at 1.
+    pub fn location(&self, offset: usize) -> (&str, u32, u32) {
+        let offset = offset.min(self.code.len());
+        let prefix = &self.code[..offset];
+        let line = prefix.matches('\n').count();
+        let column = prefix.len() - prefix.rfind('\n').map_or(0, |pos| pos + 1) + 1;
+        match self.lines.get(line) {
+            Some(&(file, line)) => (&self.files[file], line, column as u32),
+            None => (&self.files[0], line as u32 + 1, column as u32),
+        }
+    }
+
+    // Parses and validates the code the same way wgpu does when creating a
+    // shader module from it, with errors pointing at the original files.
+    pub fn validate(&self) -> anyhow::Result<(naga::Module, naga::valid::ModuleInfo)> {
+        let module = naga::front::wgsl::parse_str(&self.code).map_err(|e| {
+            let labels = e.labels().map(|(span, label)| (span, label.to_string())).collect::<Vec<_>>();
+            self.error(e.message(), &labels)
+        })?;
+        let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+            .validate(&module)
+            .map_err(|e| {
+                let mut message = e.to_string();
+                let mut source = e.source();
+                while let Some(cause) = source {
+                    message.push_str(&format!(": {}", cause));
+                    source = cause.source();
+                }
+                let labels = e.spans()
+                    .filter_map(|(span, label)| Some((span.to_range()?, label.clone())))
+                    .collect::<Vec<_>>();
+                self.error(&message, &labels)
+            })?;
+        Ok((module, info))
+    }
+
+    fn error(&self, message: &str, labels: &[(std::ops::Range<usize>, String)]) -> anyhow::Error {
+        let mut text = message.to_string();
+        for (span, label) in labels {
+            let (file, line, column) = self.location(span.start);
+            let code = self.code.lines().nth(self.code[..span.start.min(self.code.len())].matches('\n').count());
+            text.push_str(&format!("\n  {}:{}:{}: {}", file, line, column, label));
+            if let Some(code) = code {
+                text.push_str(&format!("\n    {}", code.trim()));
+            }
+        }
+        anyhow::anyhow!(text)
+    }
+}
+
+impl<'a> Expander<'a> {
+    fn expand(&mut self, path: &str, source: &str) -> anyhow::Result<()> {
+        let file = self.source.files.len();
+        self.source.files.push(path.to_string());
+        let mut conditions: Vec<Condition> = Vec::new();
+
+        for (index, line) in source.lines().enumerate() {
+            let number = index as u32 + 1;
+            let active = conditions.last().is_none_or(|condition| condition.active);
+            let trimmed = line.trim();
+            let Some(directive) = trimmed.strip_prefix('#') else {
+                if active {
+                    self.push_line(file, number, line);
+                }
+                continue;
+            };
+
+            let (name, argument) = directive.split_once(char::is_whitespace).unwrap_or((directive, ""));
+            let argument = argument.trim();
+            let fail = |message: String| anyhow::anyhow!("{}:{}: {}", path, number, message);
+            match name {
+                "ifdef" | "ifndef" => {
+                    let define = identifier(argument).map_err(fail)?;
+                    let defined = self.defines.contains_key(define);
+                    self.source.tested.insert(define.to_string());
+                    conditions.push(Condition {
+                        active: active && defined == (name == "ifdef"),
+                        parent: active,
+                        seen_else: false,
+                        line: number,
+                    });
+                }
+                "else" => {
+                    let Some(condition) = conditions.last_mut() else { return Err(fail("#else without #ifdef".into())) };
+                    if condition.seen_else {
+                        return Err(fail("second #else for the same #ifdef".into()));
+                    }
+                    condition.seen_else = true;
+                    condition.active = condition.parent && !condition.active;
+                }
+                "endif" => {
+                    if conditions.pop().is_none() {
+                        return Err(fail("#endif without #ifdef".into()));
+                    }
+                }
+                _ if !active => {}
+                "define" => {
+                    let (define, value) = argument.split_once(char::is_whitespace).unwrap_or((argument, ""));
+                    self.defines.insert(identifier(define).map_err(fail)?.to_string(), value.trim().to_string());
+                }
+                "undef" => {
+                    self.defines.remove(identifier(argument).map_err(fail)?);
+                }
+                "include" => {
+                    let Some(include) = argument.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')) else {
+                        return Err(fail(format!("expected #include \"path\", found {:?}", argument)));
+                    };
+                    let include = match path.rsplit_once('/') {
+                        Some((dir, _)) => normalize(&format!("{}/{}", dir, include)),
+                        None => normalize(include),
+                    };
+                    if self.included.insert(include.clone()) {
+                        let source = (self.read)(&include).map_err(|e| fail(format!("failed to include {}: {:#}", include, e)))?;
+                        self.expand(&include, &source)?;
+                    }
+                }
+                _ => return Err(fail(format!("unknown directive #{}", name))),
+            }
+        }
+
+        if let Some(condition) = conditions.last() {
+            bail!("{}:{}: #ifdef without #endif", path, condition.line);
+        }
+        Ok(())
+    }
+
+    fn push_line(&mut self, file: usize, number: u32, line: &str) {
+        let code = &mut self.source.code;
+        if self.defines.values().all(String::is_empty) {
+            code.push_str(line);
+        } else {
+            // Replaces whole identifiers that are defined with a value.
+            let mut rest = line;
+            while let Some(start) = rest.find(|c: char| c.is_alphabetic() || c == '_') {
+                code.push_str(&rest[..start]);
+                rest = &rest[start..];
+                let end = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+                let word = &rest[..end];
+                match self.defines.get(word) {
+                    Some(value) if !value.is_empty() => code.push_str(value),
+                    _ => code.push_str(word),
+                }
+                rest = &rest[end..];
+            }
+            code.push_str(rest);
+        }
+        code.push('\n');
+        self.source.lines.push((file, number));
+    }
+}
+
+fn identifier(name: &str) -> Result<&str, String> {
+    let valid = name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if valid {
+        Ok(name)
+    } else {
+        Err(format!("expected a name, found {:?}", name))
+    }
+}
+
+// Resolves `.` and `..` in a `/` separated asset path.
+fn normalize(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn preprocess(files: &[(&str, &str)], defines: &[(&str, &str)]) -> anyhow::Result<ShaderSource> {
+        let files: HashMap<&str, &str> = files.iter().copied().collect();
+        let mut read = |path: &str| -> anyhow::Result<String> {
+            files.get(path).map(|source| source.to_string()).ok_or_else(|| anyhow::anyhow!("no file {}", path))
+        };
+        ShaderSource::preprocess("shaders/main.wgsl", files["shaders/main.wgsl"], defines, &mut read)
+    }
+
+    fn lines(source: &ShaderSource) -> Vec<&str> {
+        source.code.lines().collect()
+    }
+
+    #[test]
+    fn includes_each_file_once() {
+        let source = preprocess(&[
+            ("shaders/main.wgsl", "#include \"a.wgsl\"\n#include \"include/b.wgsl\"\nmain"),
+            ("shaders/a.wgsl", "#include \"include/common.wgsl\"\na"),
+            ("shaders/include/b.wgsl", "#include \"../include/./common.wgsl\"\nb"),
+            ("shaders/include/common.wgsl", "common"),
+        ], &[]).unwrap();
+        assert_eq!(lines(&source), ["common", "a", "b", "main"]);
+        assert_eq!(source.files, ["shaders/main.wgsl", "shaders/a.wgsl", "shaders/include/common.wgsl", "shaders/include/b.wgsl"]);
+    }
+
+    #[test]
+    fn a_file_including_itself_is_skipped() {
+        let source = preprocess(&[("shaders/main.wgsl", "#include \"main.wgsl\"\nmain")], &[]).unwrap();
+        assert_eq!(lines(&source), ["main"]);
+    }
+
+    #[test]
+    fn nested_else_branches() {
+        let main = "#ifdef A\n#ifdef B\nab\n#else\na\n#endif\n#else\n#ifndef B\nnone\n#else\nb\n#endif\n#endif";
+        let files = [("shaders/main.wgsl", main)];
+        assert_eq!(lines(&preprocess(&files, &[("A", ""), ("B", "")]).unwrap()), ["ab"]);
+        assert_eq!(lines(&preprocess(&files, &[("A", "")]).unwrap()), ["a"]);
+        assert_eq!(lines(&preprocess(&files, &[("B", "")]).unwrap()), ["b"]);
+        assert_eq!(lines(&preprocess(&files, &[]).unwrap()), ["none"]);
+    }
+
+    #[test]
+    fn inactive_branches_are_not_expanded() {
+        let main = "#ifdef A\n#define X 1\n#include \"missing.wgsl\"\n#endif\nX";
+        let source = preprocess(&[("shaders/main.wgsl", main)], &[]).unwrap();
+        assert_eq!(lines(&source), ["X"]);
+        assert!(source.tested.contains("A"));
+    }
+
+    #[test]
+    fn defines_replace_whole_words() {
+        let main = "#define COUNT 4\nlet a = COUNT + COUNTER;";
+        let source = preprocess(&[("shaders/main.wgsl", main)], &[]).unwrap();
+        assert_eq!(lines(&source), ["let a = 4 + COUNTER;"]);
+    }
+
+    #[test]
+    fn unbalanced_conditions_fail() {
+        for main in ["#else", "#endif", "#ifdef A", "#ifdef A\n#else\n#else\n#endif"] {
+            assert!(preprocess(&[("shaders/main.wgsl", main)], &[]).is_err(), "{:?} was accepted", main);
+        }
+    }
+
+    #[test]
+    fn locations_point_at_the_original_lines() {
+        let source = preprocess(&[
+            ("shaders/main.wgsl", "// main\n#ifdef A\nskipped\n#endif\n#include \"lib.wgsl\"\nfirst\nsecond"),
+            ("shaders/lib.wgsl", "\nlib"),
+        ], &[]).unwrap();
+        assert_eq!(lines(&source), ["// main", "", "lib", "first", "second"]);
+        let offset = |text: &str| source.code.find(text).unwrap();
+        assert_eq!(source.location(offset("lib")), ("shaders/lib.wgsl", 2, 1));
+        assert_eq!(source.location(offset("first")), ("shaders/main.wgsl", 6, 1));
+        assert_eq!(source.location(offset("second") + 2), ("shaders/main.wgsl", 7, 3));
+    }
+}
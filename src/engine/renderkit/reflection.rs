@@ -0,0 +1,366 @@
+use std::num::{NonZeroU32, NonZeroU64};
+
+use anyhow::{bail, Context};
+
+use super::{gpuerror::GpuError, gpuhandle::GPUHandle};
+
+// A resource a shader declares with `@group(g) @binding(b)`.
+#[derive(Clone, Debug)]
+pub struct ReflectedBinding {
+    // The name of the global variable.
+    pub name: String,
+    pub group: u32,
+    pub binding: u32,
+    pub ty: wgpu::BindingType,
+    pub count: Option<NonZeroU32>,
+    // The entry points that use it and their stages.
+    used_by: Vec<(String, wgpu::ShaderStages)>,
+}
+
+impl ReflectedBinding {
+    pub fn visibility(&self) -> wgpu::ShaderStages {
+        self.used_by.iter().fold(wgpu::ShaderStages::NONE, |stages, (_, stage)| stages | *stage)
+    }
+
+    pub fn layout_entry(&self) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding: self.binding,
+            visibility: self.visibility(),
+            ty: self.ty,
+            count: self.count,
+        }
+    }
+}
+
+// The bind group layouts of a shader, read from its `@group/@binding`
+// declarations with naga so they don't have to be kept in sync by hand.
+#[derive(Clone, Debug, Default)]
+pub struct ShaderLayout {
+    // Sorted by group and binding.
+    bindings: Vec<ReflectedBinding>,
+}
+
+impl ShaderLayout {
+    pub fn reflect(module: &naga::Module, info: &naga::valid::ModuleInfo) -> anyhow::Result<ShaderLayout> {
+        let mut bindings = Vec::new();
+        for (handle, global) in module.global_variables.iter() {
+            let Some(binding) = &global.binding else { continue };
+            let name = global.name.clone().unwrap_or_default();
+            let (ty, count) = binding_type(module, global)
+                .with_context(|| format!("can't bind {:?} at group {} binding {}", name, binding.group, binding.binding))?;
+            let used_by = module.entry_points.iter()
+                .enumerate()
+                .filter(|&(index, _)| !info.get_entry_point(index)[handle].is_empty())
+                .map(|(_, entry_point)| (entry_point.name.clone(), shader_stage(entry_point.stage)))
+                .collect();
+            bindings.push(ReflectedBinding {
+                name,
+                group: binding.group,
+                binding: binding.binding,
+                ty,
+                count,
+                used_by,
+            });
+        }
+        bindings.sort_by_key(|binding| (binding.group, binding.binding));
+        Ok(ShaderLayout { bindings })
+    }
+
+    // Only what `entry_points` use, for pipelines that don't use every entry
+    // point of the shader.
+    pub fn for_entry_points(&self, entry_points: &[&str]) -> ShaderLayout {
+        let bindings = self.bindings.iter()
+            .filter_map(|binding| {
+                let used_by = binding.used_by.iter()
+                    .filter(|(name, _)| entry_points.contains(&name.as_str()))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                (!used_by.is_empty()).then(|| ReflectedBinding { used_by, ..binding.clone() })
+            })
+            .collect();
+        ShaderLayout { bindings }
+    }
+
+    pub fn group(&self, group: u32) -> impl Iterator<Item = &ReflectedBinding> {
+        self.bindings.iter().filter(move |binding| binding.group == group)
+    }
+
+    pub fn binding(&self, group: u32, name: &str) -> Option<&ReflectedBinding> {
+        self.group(group).find(|binding| binding.name == name)
+    }
+
+    pub fn entries(&self, group: u32) -> Vec<wgpu::BindGroupLayoutEntry> {
+        self.group(group).map(ReflectedBinding::layout_entry).collect()
+    }
+
+    pub fn create_layout(&self, group: u32, gpu: &GPUHandle, label: &str) -> Result<wgpu::BindGroupLayout, GpuError> {
+        gpu.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(label),
+            entries: &self.entries(group),
+        })
+    }
+
+    // Checks that a handwritten layout has every binding the shader uses in
+    // `group`, with a compatible type and visible to the stages using it.
+    // Entries the shader doesn't use are fine.
+    pub fn verify(&self, group: u32, entries: &[wgpu::BindGroupLayoutEntry]) -> anyhow::Result<()> {
+        for reflected in self.group(group) {
+            let Some(entry) = entries.iter().find(|entry| entry.binding == reflected.binding) else {
+                bail!("group {} has no binding {} for {}", group, reflected.binding, reflected.name);
+            };
+            if !compatible(&entry.ty, &reflected.ty) || entry.count != reflected.count {
+                bail!(
+                    "group {} binding {} ({}) is {:?} in the layout but {:?} in the shader",
+                    group, reflected.binding, reflected.name, entry.ty, reflected.ty,
+                );
+            }
+            if !entry.visibility.contains(reflected.visibility()) {
+                bail!(
+                    "group {} binding {} ({}) is visible to {:?} but used by {:?}",
+                    group, reflected.binding, reflected.name, entry.visibility, reflected.visibility(),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // Starts a bind group for `group` whose resources are given by name.
+    pub fn bind_group<'a>(&'a self, group: u32, layout: &'a wgpu::BindGroupLayout, label: &'a str) -> BindGroupBuilder<'a> {
+        BindGroupBuilder {
+            shader: self,
+            group,
+            layout,
+            label,
+            entries: Vec::new(),
+            error: None,
+        }
+    }
+
+    // Fails unless the shader has a binding for every name in `group`.
+    pub fn require(&self, group: u32, names: &[&str]) -> anyhow::Result<()> {
+        for name in names {
+            if self.binding(group, name).is_none() {
+                bail!("the shader has no binding {:?} in group {}", name, group);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Builds a bind group by binding name, checking each resource against the
+// type the shader declared for it. The first mistake is returned by `build`.
+pub struct BindGroupBuilder<'a> {
+    shader: &'a ShaderLayout,
+    group: u32,
+    layout: &'a wgpu::BindGroupLayout,
+    label: &'a str,
+    entries: Vec<wgpu::BindGroupEntry<'a>>,
+    error: Option<String>,
+}
+
+impl<'a> BindGroupBuilder<'a> {
+    pub fn buffer(self, name: &str, buffer: &'a wgpu::Buffer) -> Self {
+        self.bind(name, "a buffer", |ty| matches!(ty, wgpu::BindingType::Buffer { .. }), buffer.as_entire_binding())
+    }
+
+    pub fn texture(self, name: &str, view: &'a wgpu::TextureView) -> Self {
+        let is_texture = |ty: &wgpu::BindingType| matches!(ty, wgpu::BindingType::Texture { .. } | wgpu::BindingType::StorageTexture { .. });
+        self.bind(name, "a texture", is_texture, wgpu::BindingResource::TextureView(view))
+    }
+
+    pub fn sampler(self, name: &str, sampler: &'a wgpu::Sampler) -> Self {
+        self.bind(name, "a sampler", |ty| matches!(ty, wgpu::BindingType::Sampler(_)), wgpu::BindingResource::Sampler(sampler))
+    }
+
+    fn bind(mut self, name: &str, kind: &str, accepts: impl Fn(&wgpu::BindingType) -> bool, resource: wgpu::BindingResource<'a>) -> Self {
+        if self.error.is_some() {
+            return self;
+        }
+        match self.shader.binding(self.group, name) {
+            Some(binding) if accepts(&binding.ty) => {
+                self.entries.push(wgpu::BindGroupEntry { binding: binding.binding, resource });
+            }
+            Some(binding) => {
+                self.error = Some(format!("{} is {:?}, not {}", name, binding.ty, kind));
+            }
+            None => {
+                self.error = Some(format!("the shader has no binding {:?} in group {}", name, self.group));
+            }
+        }
+        self
+    }
+
+    pub fn build(self, gpu: &GPUHandle) -> Result<wgpu::BindGroup, GpuError> {
+        let bound = self.entries.iter().map(|entry| entry.binding).collect::<Vec<_>>();
+        let missing = self.shader.group(self.group)
+            .find(|binding| !bound.contains(&binding.binding))
+            .map(|binding| format!("nothing is bound to {}", binding.name));
+        if let Some(description) = self.error.or(missing) {
+            return Err(GpuError::Validation {
+                kind: "bind group",
+                label: self.label.to_string(),
+                description,
+            });
+        }
+        gpu.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(self.label),
+            layout: self.layout,
+            entries: &self.entries,
+        })
+    }
+}
+
+fn shader_stage(stage: naga::ShaderStage) -> wgpu::ShaderStages {
+    match stage {
+        naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+        naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+        naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+    }
+}
+
+fn binding_type(module: &naga::Module, global: &naga::GlobalVariable) -> anyhow::Result<(wgpu::BindingType, Option<NonZeroU32>)> {
+    let (ty, count) = match &module.types[global.ty].inner {
+        naga::TypeInner::BindingArray { base, size } => {
+            let count = match size {
+                naga::ArraySize::Constant(constant) => match module.constants[*constant].inner {
+                    naga::ConstantInner::Scalar { value: naga::ScalarValue::Uint(count), .. } => count as u32,
+                    naga::ConstantInner::Scalar { value: naga::ScalarValue::Sint(count), .. } => count as u32,
+                    _ => bail!("binding array size isn't an integer"),
+                },
+                naga::ArraySize::Dynamic => bail!("binding arrays need a fixed size"),
+            };
+            (*base, NonZeroU32::new(count))
+        }
+        _ => (global.ty, None),
+    };
+
+    let binding = match global.space {
+        naga::AddressSpace::Uniform | naga::AddressSpace::Storage { .. } => {
+            let buffer = match global.space {
+                naga::AddressSpace::Storage { access } => wgpu::BufferBindingType::Storage {
+                    read_only: !access.contains(naga::StorageAccess::STORE),
+                },
+                _ => wgpu::BufferBindingType::Uniform,
+            };
+            wgpu::BindingType::Buffer {
+                ty: buffer,
+                has_dynamic_offset: false,
+                min_binding_size: NonZeroU64::new(module.types[ty].inner.size(&module.constants) as u64),
+            }
+        }
+        naga::AddressSpace::Handle => match &module.types[ty].inner {
+            naga::TypeInner::Sampler { comparison: true } => wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+            naga::TypeInner::Sampler { comparison: false } => wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            naga::TypeInner::Image { dim, arrayed, class } => {
+                let view_dimension = view_dimension(*dim, *arrayed)?;
+                match class {
+                    naga::ImageClass::Sampled { kind, multi } => wgpu::BindingType::Texture {
+                        sample_type: match kind {
+                            naga::ScalarKind::Float => wgpu::TextureSampleType::Float { filterable: true },
+                            naga::ScalarKind::Sint => wgpu::TextureSampleType::Sint,
+                            naga::ScalarKind::Uint => wgpu::TextureSampleType::Uint,
+                            naga::ScalarKind::Bool => bail!("textures can't hold booleans"),
+                        },
+                        view_dimension,
+                        multisampled: *multi,
+                    },
+                    naga::ImageClass::Depth { multi } => wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension,
+                        multisampled: *multi,
+                    },
+                    naga::ImageClass::Storage { format, access } => wgpu::BindingType::StorageTexture {
+                        access: match (access.contains(naga::StorageAccess::LOAD), access.contains(naga::StorageAccess::STORE)) {
+                            (true, true) => wgpu::StorageTextureAccess::ReadWrite,
+                            (true, false) => wgpu::StorageTextureAccess::ReadOnly,
+                            _ => wgpu::StorageTextureAccess::WriteOnly,
+                        },
+                        format: storage_format(*format),
+                        view_dimension,
+                    },
+                }
+            }
+            other => bail!("unsupported resource type {:?}", other),
+        },
+        other => bail!("{:?} variables can't be bound", other),
+    };
+    Ok((binding, count))
+}
+
+fn view_dimension(dim: naga::ImageDimension, arrayed: bool) -> anyhow::Result<wgpu::TextureViewDimension> {
+    Ok(match (dim, arrayed) {
+        (naga::ImageDimension::D1, false) => wgpu::TextureViewDimension::D1,
+        (naga::ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+        (naga::ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+        (naga::ImageDimension::D3, false) => wgpu::TextureViewDimension::D3,
+        (naga::ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+        (naga::ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+        (dim, _) => bail!("{:?} textures can't be arrayed", dim),
+    })
+}
+
+fn storage_format(format: naga::StorageFormat) -> wgpu::TextureFormat {
+    use naga::StorageFormat as S;
+    use wgpu::TextureFormat as T;
+    match format {
+        S::R8Unorm => T::R8Unorm,
+        S::R8Snorm => T::R8Snorm,
+        S::R8Uint => T::R8Uint,
+        S::R8Sint => T::R8Sint,
+        S::R16Uint => T::R16Uint,
+        S::R16Sint => T::R16Sint,
+        S::R16Float => T::R16Float,
+        S::Rg8Unorm => T::Rg8Unorm,
+        S::Rg8Snorm => T::Rg8Snorm,
+        S::Rg8Uint => T::Rg8Uint,
+        S::Rg8Sint => T::Rg8Sint,
+        S::R32Uint => T::R32Uint,
+        S::R32Sint => T::R32Sint,
+        S::R32Float => T::R32Float,
+        S::Rg16Uint => T::Rg16Uint,
+        S::Rg16Sint => T::Rg16Sint,
+        S::Rg16Float => T::Rg16Float,
+        S::Rgba8Unorm => T::Rgba8Unorm,
+        S::Rgba8Snorm => T::Rgba8Snorm,
+        S::Rgba8Uint => T::Rgba8Uint,
+        S::Rgba8Sint => T::Rgba8Sint,
+        S::Rgb10a2Unorm => T::Rgb10a2Unorm,
+        S::Rg11b10Float => T::Rg11b10Float,
+        S::Rg32Uint => T::Rg32Uint,
+        S::Rg32Sint => T::Rg32Sint,
+        S::Rg32Float => T::Rg32Float,
+        S::Rgba16Uint => T::Rgba16Uint,
+        S::Rgba16Sint => T::Rgba16Sint,
+        S::Rgba16Float => T::Rgba16Float,
+        S::Rgba32Uint => T::Rgba32Uint,
+        S::Rgba32Sint => T::Rgba32Sint,
+        S::Rgba32Float => T::Rgba32Float,
+    }
+}
+
+// Whether a handwritten binding type works for what the shader declared.
+// Reflection can't tell everything the layout decides: whether a buffer has
+// a dynamic offset or a minimum size, and whether a float texture or a
+// sampler filters, which depends on how they're used together.
+fn compatible(layout: &wgpu::BindingType, shader: &wgpu::BindingType) -> bool {
+    use wgpu::BindingType as B;
+    match (layout, shader) {
+        (B::Buffer { ty: a, min_binding_size, .. }, B::Buffer { ty: b, min_binding_size: needed, .. }) => {
+            a == b && min_binding_size.zip(*needed).is_none_or(|(size, needed)| size >= needed)
+        }
+        (B::Sampler(a), B::Sampler(b)) => {
+            (*a == wgpu::SamplerBindingType::Comparison) == (*b == wgpu::SamplerBindingType::Comparison)
+        }
+        (
+            B::Texture { sample_type: a, view_dimension: a_dimension, multisampled: a_multi },
+            B::Texture { sample_type: b, view_dimension: b_dimension, multisampled: b_multi },
+        ) => {
+            let same_type = match (a, b) {
+                (wgpu::TextureSampleType::Float { .. }, wgpu::TextureSampleType::Float { .. }) => true,
+                _ => a == b,
+            };
+            same_type && a_dimension == b_dimension && a_multi == b_multi
+        }
+        _ => layout == shader,
+    }
+}
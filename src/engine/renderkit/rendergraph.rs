@@ -0,0 +1,634 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use anyhow::bail;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ResourceId(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PassId(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TextureDesc {
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+    pub usage: wgpu::TextureUsages,
+    pub sample_count: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BufferDesc {
+    pub size: wgpu::BufferAddress,
+    pub usage: wgpu::BufferUsages,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ResourceDesc {
+    Texture(TextureDesc),
+    Buffer(BufferDesc),
+}
+
+enum ResourceKind<'a> {
+    Transient(ResourceDesc),
+    ImportedTexture(&'a wgpu::TextureView),
+    ImportedBuffer(&'a wgpu::Buffer),
+}
+
+struct ResourceNode<'a> {
+    name: String,
+    kind: ResourceKind<'a>,
+    output: bool,
+}
+
+type PassFn<'a> = Box<dyn FnOnce(&PassResources, &mut wgpu::CommandEncoder) + 'a>;
+
+struct PassNode<'a> {
+    name: String,
+    reads: Vec<ResourceId>,
+    writes: Vec<ResourceId>,
+    execute: PassFn<'a>,
+}
+
+// The result of ordering and culling the graph. `slots` maps every transient
+// resource that survives culling to a physical allocation; resources whose
+// lifetimes don't overlap and share a descriptor end up in the same slot.
+struct CompiledGraph {
+    order: Vec<usize>,
+    culled: Vec<bool>,
+    slots: HashMap<usize, (ResourceDesc, usize)>,
+}
+
+// Declarative description of a frame. Passes declare the resources they read
+// and write, and the graph works out the execution order, drops passes that
+// don't contribute to an output, and hands out transient attachments.
+pub struct RenderGraph<'a> {
+    resources: Vec<ResourceNode<'a>>,
+    passes: Vec<PassNode<'a>>,
+}
+
+impl<'a> Default for RenderGraph<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        RenderGraph {
+            resources: Vec::new(),
+            passes: Vec::new(),
+        }
+    }
+
+    pub fn create_texture(&mut self, name: &str, desc: TextureDesc) -> ResourceId {
+        self.add_resource(name, ResourceKind::Transient(ResourceDesc::Texture(desc)))
+    }
+
+    pub fn create_buffer(&mut self, name: &str, desc: BufferDesc) -> ResourceId {
+        self.add_resource(name, ResourceKind::Transient(ResourceDesc::Buffer(desc)))
+    }
+
+    // Imported resources live outside the graph (e.g. the swapchain view) and
+    // always count as outputs, so passes writing to them are never culled.
+    pub fn import_texture(&mut self, name: &str, view: &'a wgpu::TextureView) -> ResourceId {
+        let id = self.add_resource(name, ResourceKind::ImportedTexture(view));
+        self.mark_output(id);
+        id
+    }
+
+    pub fn import_buffer(&mut self, name: &str, buffer: &'a wgpu::Buffer) -> ResourceId {
+        let id = self.add_resource(name, ResourceKind::ImportedBuffer(buffer));
+        self.mark_output(id);
+        id
+    }
+
+    pub fn mark_output(&mut self, id: ResourceId) {
+        self.resources[id.0].output = true;
+    }
+
+    pub fn add_pass<F>(&mut self, name: &str, reads: &[ResourceId], writes: &[ResourceId], execute: F) -> PassId
+    where
+        F: FnOnce(&PassResources, &mut wgpu::CommandEncoder) + 'a,
+    {
+        self.passes.push(PassNode {
+            name: name.to_string(),
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+            execute: Box::new(execute),
+        });
+        PassId(self.passes.len() - 1)
+    }
+
+    pub fn execute(
+        self,
+        device: &wgpu::Device,
+        pool: &mut TransientPool,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> anyhow::Result<()> {
+        let compiled = self.compile()?;
+
+        let mut needed: HashMap<ResourceDesc, usize> = HashMap::new();
+        for (desc, slot) in compiled.slots.values() {
+            let count = needed.entry(*desc).or_insert(0);
+            *count = (*count).max(slot + 1);
+        }
+        pool.trim(&needed);
+        for (desc, count) in &needed {
+            pool.ensure(device, *desc, *count);
+        }
+
+        let mut resources = PassResources {
+            textures: vec![None; self.resources.len()],
+            buffers: vec![None; self.resources.len()],
+            generation: pool.generation,
+        };
+        for (index, resource) in self.resources.iter().enumerate() {
+            match resource.kind {
+                ResourceKind::ImportedTexture(view) => resources.textures[index] = Some(view),
+                ResourceKind::ImportedBuffer(buffer) => resources.buffers[index] = Some(buffer),
+                ResourceKind::Transient(_) => {
+                    if let Some((desc, slot)) = compiled.slots.get(&index) {
+                        match &pool.entries[desc][*slot] {
+                            PhysicalResource::Texture { view, .. } => resources.textures[index] = Some(view),
+                            PhysicalResource::Buffer(buffer) => resources.buffers[index] = Some(buffer),
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut passes: Vec<Option<PassNode>> = self.passes.into_iter().map(Some).collect();
+        for index in compiled.order {
+            if let Some(pass) = passes[index].take() {
+                encoder.push_debug_group(&pass.name);
+                (pass.execute)(&resources, encoder);
+                encoder.pop_debug_group();
+            }
+        }
+
+        Ok(())
+    }
+
+    // Dumps the graph in Graphviz DOT format. Culled passes are drawn greyed
+    // out and each transient resource is annotated with its physical slot.
+    pub fn to_dot(&self) -> String {
+        let compiled = self.compile().ok();
+        let mut dot = String::from("digraph RenderGraph {\n    rankdir=LR;\n");
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            let culled = compiled.as_ref().is_some_and(|c| c.culled[index]);
+            let style = if culled { ", style=dashed, fontcolor=gray, color=gray" } else { "" };
+            writeln!(dot, "    p{} [label=\"{}\", shape=box{}];", index, escape(&pass.name), style).unwrap();
+        }
+
+        for (index, resource) in self.resources.iter().enumerate() {
+            let name = escape(&resource.name);
+            let label = match &resource.kind {
+                ResourceKind::Transient(desc) => {
+                    let slot = compiled
+                        .as_ref()
+                        .and_then(|c| c.slots.get(&index))
+                        .map_or(String::from("unused"), |(_, slot)| format!("slot {}", slot));
+                    match desc {
+                        ResourceDesc::Texture(t) => format!("{}\\n{}x{} {:?} x{}\\n{}", name, t.width, t.height, t.format, t.sample_count, slot),
+                        ResourceDesc::Buffer(b) => format!("{}\\n{} bytes\\n{}", name, b.size, slot),
+                    }
+                }
+                _ => format!("{}\\nimported", name),
+            };
+            let peripheries = if resource.output { 2 } else { 1 };
+            writeln!(dot, "    r{} [label=\"{}\", shape=ellipse, peripheries={}];", index, label, peripheries).unwrap();
+        }
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            for read in &pass.reads {
+                writeln!(dot, "    r{} -> p{};", read.0, index).unwrap();
+            }
+            for write in &pass.writes {
+                writeln!(dot, "    p{} -> r{};", index, write.0).unwrap();
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn add_resource(&mut self, name: &str, kind: ResourceKind<'a>) -> ResourceId {
+        self.resources.push(ResourceNode {
+            name: name.to_string(),
+            kind,
+            output: false,
+        });
+        ResourceId(self.resources.len() - 1)
+    }
+
+    // A pure reader depends on the last pass declared before it that writes
+    // the resource, or on every writer if none was declared earlier, so a
+    // consumer can be added before its producer. Writers run after earlier
+    // writers and after any earlier readers of the previous contents.
+    fn dependencies(&self) -> Vec<Vec<usize>> {
+        let mut writers: Vec<Vec<usize>> = vec![Vec::new(); self.resources.len()];
+        for (index, pass) in self.passes.iter().enumerate() {
+            for write in &pass.writes {
+                writers[write.0].push(index);
+            }
+        }
+
+        let mut deps: Vec<Vec<usize>> = vec![Vec::new(); self.passes.len()];
+        let mut readers: Vec<Vec<(usize, Vec<usize>)>> = vec![Vec::new(); self.resources.len()];
+        for (index, pass) in self.passes.iter().enumerate() {
+            for read in &pass.reads {
+                if pass.writes.contains(read) {
+                    continue;
+                }
+                let producers: Vec<usize> = match writers[read.0].iter().rev().find(|w| **w < index) {
+                    Some(w) => vec![*w],
+                    None => writers[read.0].clone(),
+                };
+                deps[index].extend(producers.iter().copied());
+                readers[read.0].push((index, producers));
+            }
+        }
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            for write in &pass.writes {
+                deps[index].extend(writers[write.0].iter().copied().filter(|w| *w < index));
+                for (reader, producers) in &readers[write.0] {
+                    if *reader < index && !producers.contains(&index) {
+                        deps[index].push(*reader);
+                    }
+                }
+            }
+            deps[index].sort_unstable();
+            deps[index].dedup();
+        }
+
+        deps
+    }
+
+    fn compile(&self) -> anyhow::Result<CompiledGraph> {
+        let deps = self.dependencies();
+
+        // Walk back from every pass that writes an output; anything we don't
+        // reach has no observable effect.
+        let mut culled = vec![true; self.passes.len()];
+        let mut stack: Vec<usize> = self
+            .passes
+            .iter()
+            .enumerate()
+            .filter(|(_, pass)| pass.writes.iter().any(|w| self.resources[w.0].output))
+            .map(|(index, _)| index)
+            .collect();
+        while let Some(index) = stack.pop() {
+            if !culled[index] {
+                continue;
+            }
+            culled[index] = false;
+            stack.extend(deps[index].iter().copied());
+        }
+
+        // Kahn's algorithm, always picking the earliest declared ready pass so
+        // the order is stable between frames.
+        let mut remaining: Vec<usize> = deps
+            .iter()
+            .enumerate()
+            .map(|(index, d)| if culled[index] { 0 } else { d.len() })
+            .collect();
+        let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); self.passes.len()];
+        for (index, d) in deps.iter().enumerate() {
+            if culled[index] {
+                continue;
+            }
+            for dep in d {
+                dependents[*dep].push(index);
+            }
+        }
+
+        let mut order = Vec::new();
+        let mut ready: Vec<usize> = (0..self.passes.len())
+            .filter(|index| !culled[*index] && remaining[*index] == 0)
+            .collect();
+        while !ready.is_empty() {
+            ready.sort_unstable_by(|a, b| b.cmp(a));
+            let index = ready.pop().unwrap();
+            order.push(index);
+            for dependent in &dependents[index] {
+                remaining[*dependent] -= 1;
+                if remaining[*dependent] == 0 {
+                    ready.push(*dependent);
+                }
+            }
+        }
+
+        let live = culled.iter().filter(|c| !**c).count();
+        if order.len() != live {
+            let stuck: Vec<&str> = (0..self.passes.len())
+                .filter(|index| !culled[*index] && !order.contains(index))
+                .map(|index| self.passes[index].name.as_str())
+                .collect();
+            bail!("render graph has a cycle between passes: {}", stuck.join(", "));
+        }
+
+        // First and last use of each transient, measured in execution order.
+        // Outputs are read after the graph has run, so they live to the end.
+        let mut lifetimes: HashMap<usize, (usize, usize)> = HashMap::new();
+        for (position, index) in order.iter().enumerate() {
+            let pass = &self.passes[*index];
+            for id in pass.reads.iter().chain(pass.writes.iter()) {
+                let resource = &self.resources[id.0];
+                if let ResourceKind::Transient(_) = resource.kind {
+                    let lifetime = lifetimes.entry(id.0).or_insert((position, position));
+                    lifetime.1 = if resource.output { order.len() } else { position };
+                }
+            }
+        }
+
+        let mut by_first_use: Vec<(usize, (usize, usize))> = lifetimes.into_iter().collect();
+        by_first_use.sort_unstable_by_key(|(id, (first, _))| (*first, *id));
+
+        // Greedy aliasing: reuse the first slot with a matching descriptor
+        // whose previous occupant is already dead.
+        let mut slot_ends: HashMap<ResourceDesc, Vec<usize>> = HashMap::new();
+        let mut slots = HashMap::new();
+        for (id, (first, last)) in by_first_use {
+            let desc = match self.resources[id].kind {
+                ResourceKind::Transient(desc) => desc,
+                _ => unreachable!(),
+            };
+            let ends = slot_ends.entry(desc).or_default();
+            let slot = match ends.iter().position(|end| *end < first) {
+                Some(slot) => {
+                    ends[slot] = last;
+                    slot
+                }
+                None => {
+                    ends.push(last);
+                    ends.len() - 1
+                }
+            };
+            slots.insert(id, (desc, slot));
+        }
+
+        Ok(CompiledGraph { order, culled, slots })
+    }
+}
+
+// Escapes a name for a quoted DOT string.
+fn escape(name: &str) -> String {
+    name.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+pub struct PassResources<'r> {
+    textures: Vec<Option<&'r wgpu::TextureView>>,
+    buffers: Vec<Option<&'r wgpu::Buffer>>,
+    generation: u64,
+}
+
+impl<'r> PassResources<'r> {
+    pub fn texture(&self, id: ResourceId) -> &'r wgpu::TextureView {
+        self.textures[id.0].expect("render graph resource is not a live texture")
+    }
+
+    pub fn buffer(&self, id: ResourceId) -> &'r wgpu::Buffer {
+        self.buffers[id.0].expect("render graph resource is not a live buffer")
+    }
+
+    // Changes whenever a transient was created or dropped, so anything made
+    // from transient views, e.g. bind groups, can be kept until then.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+enum PhysicalResource {
+    // The texture is only held so it lives as long as its view.
+    Texture { _texture: wgpu::Texture, view: wgpu::TextureView },
+    Buffer(wgpu::Buffer),
+}
+
+// Transient allocations are kept around between frames so a graph that looks
+// the same every frame doesn't recreate its attachments. Ones the last graph
+// didn't use are dropped, e.g. the old sizes after a resize.
+pub struct TransientPool {
+    entries: HashMap<ResourceDesc, Vec<PhysicalResource>>,
+    generation: u64,
+}
+
+impl Default for TransientPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TransientPool {
+    pub fn new() -> Self {
+        TransientPool {
+            entries: HashMap::new(),
+            generation: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.generation += 1;
+    }
+
+    // Drops every allocation beyond what `needed` asks for.
+    fn trim(&mut self, needed: &HashMap<ResourceDesc, usize>) {
+        let mut dropped = false;
+        self.entries.retain(|desc, entries| {
+            let count = needed.get(desc).copied().unwrap_or(0);
+            dropped |= entries.len() > count;
+            entries.truncate(count);
+            !entries.is_empty()
+        });
+        if dropped {
+            self.generation += 1;
+        }
+    }
+
+    fn ensure(&mut self, device: &wgpu::Device, desc: ResourceDesc, count: usize) {
+        let entries = self.entries.entry(desc).or_default();
+        while entries.len() < count {
+            let resource = match desc {
+                ResourceDesc::Texture(t) => {
+                    let texture = device.create_texture(&wgpu::TextureDescriptor {
+                        label: Some("transient_texture"),
+                        size: wgpu::Extent3d {
+                            width: t.width,
+                            height: t.height,
+                            depth_or_array_layers: 1,
+                        },
+                        mip_level_count: 1,
+                        sample_count: t.sample_count,
+                        dimension: wgpu::TextureDimension::D2,
+                        format: t.format,
+                        usage: t.usage,
+                    });
+                    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                    PhysicalResource::Texture { _texture: texture, view }
+                }
+                ResourceDesc::Buffer(b) => PhysicalResource::Buffer(device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("transient_buffer"),
+                    size: b.size,
+                    usage: b.usage,
+                    mapped_at_creation: false,
+                })),
+            };
+            entries.push(resource);
+            self.generation += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn desc(width: u32) -> TextureDesc {
+        TextureDesc {
+            width,
+            height: width,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            sample_count: 1,
+        }
+    }
+
+    fn pass(graph: &mut RenderGraph, name: &str, reads: &[ResourceId], writes: &[ResourceId]) {
+        graph.add_pass(name, reads, writes, |_, _| {});
+    }
+
+    fn order(graph: &RenderGraph) -> Vec<String> {
+        graph.compile().unwrap().order.iter().map(|&index| graph.passes[index].name.clone()).collect()
+    }
+
+    #[test]
+    fn consumers_run_after_producers_declared_later() {
+        let mut graph = RenderGraph::new();
+        let (a, b, out) = (graph.create_texture("a", desc(1)), graph.create_texture("b", desc(1)), graph.create_texture("out", desc(1)));
+        graph.mark_output(out);
+        pass(&mut graph, "final", &[b], &[out]);
+        pass(&mut graph, "middle", &[a], &[b]);
+        pass(&mut graph, "first", &[], &[a]);
+        assert_eq!(order(&graph), ["first", "middle", "final"]);
+    }
+
+    #[test]
+    fn independent_passes_keep_their_declaration_order() {
+        let mut graph = RenderGraph::new();
+        let outputs = (0..3).map(|i| graph.create_texture(&format!("out{}", i), desc(1))).collect::<Vec<_>>();
+        for (i, &output) in outputs.iter().enumerate().rev() {
+            graph.mark_output(output);
+            pass(&mut graph, &format!("pass{}", i), &[], &[output]);
+        }
+        assert_eq!(order(&graph), ["pass2", "pass1", "pass0"]);
+    }
+
+    #[test]
+    fn writers_wait_for_readers_of_the_previous_contents() {
+        let mut graph = RenderGraph::new();
+        let (shared, copy, out) = (graph.create_texture("shared", desc(1)), graph.create_texture("copy", desc(1)), graph.create_texture("out", desc(1)));
+        graph.mark_output(out);
+        graph.mark_output(copy);
+        pass(&mut graph, "write", &[], &[shared]);
+        pass(&mut graph, "read", &[shared], &[copy]);
+        pass(&mut graph, "overwrite", &[], &[shared]);
+        pass(&mut graph, "final", &[shared], &[out]);
+        assert_eq!(order(&graph), ["write", "read", "overwrite", "final"]);
+    }
+
+    #[test]
+    fn passes_that_reach_no_output_are_culled() {
+        let mut graph = RenderGraph::new();
+        let (used, unused, scratch, out) = (
+            graph.create_texture("used", desc(1)),
+            graph.create_texture("unused", desc(1)),
+            graph.create_texture("scratch", desc(1)),
+            graph.create_texture("out", desc(1)),
+        );
+        graph.mark_output(out);
+        pass(&mut graph, "scratch", &[], &[scratch]);
+        pass(&mut graph, "dead", &[scratch], &[unused]);
+        pass(&mut graph, "live", &[], &[used]);
+        pass(&mut graph, "final", &[used], &[out]);
+
+        let compiled = graph.compile().unwrap();
+        assert_eq!(compiled.culled, [true, true, false, false]);
+        assert_eq!(order(&graph), ["live", "final"]);
+        // Culled passes don't keep their resources alive.
+        assert!(!compiled.slots.contains_key(&scratch.0) && !compiled.slots.contains_key(&unused.0));
+    }
+
+    #[test]
+    fn cycles_are_reported() {
+        let mut graph = RenderGraph::new();
+        let (x, y) = (graph.create_texture("x", desc(1)), graph.create_texture("y", desc(1)));
+        graph.mark_output(x);
+        graph.mark_output(y);
+        pass(&mut graph, "a", &[x], &[y]);
+        pass(&mut graph, "b", &[y], &[x]);
+        let error = graph.compile().err().unwrap();
+        assert!(error.to_string().contains("cycle between passes: a, b"), "{}", error);
+    }
+
+    #[test]
+    fn transients_share_slots_when_their_lifetimes_dont_overlap() {
+        let mut graph = RenderGraph::new();
+        let (a, b, c, other, out) = (
+            graph.create_texture("a", desc(1)),
+            graph.create_texture("b", desc(1)),
+            graph.create_texture("c", desc(1)),
+            graph.create_texture("other", desc(2)),
+            graph.create_texture("out", desc(1)),
+        );
+        graph.mark_output(out);
+        pass(&mut graph, "1", &[], &[a]);
+        pass(&mut graph, "2", &[a], &[b]);
+        pass(&mut graph, "3", &[b], &[c, other]);
+        pass(&mut graph, "4", &[c, other], &[out]);
+
+        let slots = graph.compile().unwrap().slots;
+        let slot = |id: ResourceId| slots[&id.0].1;
+        // `a` is dead once `b` is written, and `b` once `c` is.
+        assert_eq!(slot(a), 0);
+        assert_eq!(slot(b), 1);
+        assert_eq!(slot(c), 0);
+        assert_eq!(slot(out), 1);
+        // Only textures with the same descriptor are aliased.
+        assert_eq!(slot(other), 0);
+        assert_eq!(slots[&other.0].0, ResourceDesc::Texture(desc(2)));
+    }
+
+    #[test]
+    fn outputs_are_never_aliased_by_later_transients() {
+        let mut graph = RenderGraph::new();
+        let (out, scratch, result) = (
+            graph.create_texture("out", desc(1)),
+            graph.create_texture("scratch", desc(1)),
+            graph.create_texture("result", desc(2)),
+        );
+        graph.mark_output(out);
+        graph.mark_output(result);
+        pass(&mut graph, "1", &[], &[out]);
+        pass(&mut graph, "2", &[], &[scratch]);
+        pass(&mut graph, "3", &[scratch], &[result]);
+
+        let slots = graph.compile().unwrap().slots;
+        // `out` is never read again inside the graph, but must survive it.
+        assert_ne!(slots[&out.0].1, slots[&scratch.0].1);
+    }
+
+    #[test]
+    fn dot_output_escapes_names() {
+        let mut graph = RenderGraph::new();
+        let out = graph.create_texture("say \"hi\"", desc(1));
+        graph.mark_output(out);
+        pass(&mut graph, "back\\slash", &[], &[out]);
+        let dot = graph.to_dot();
+        assert!(dot.contains("p0 [label=\"back\\\\slash\", shape=box];"), "{}", dot);
+        assert!(dot.contains("r0 [label=\"say \\\"hi\\\"\\n"), "{}", dot);
+    }
+}
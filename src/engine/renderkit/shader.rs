@@ -0,0 +1,74 @@
+use super::{gpuerror::GpuError, gpuhandle::GPUHandle, preprocessor::ShaderSource, reflection::ShaderLayout};
+
+pub struct Shader {
+    pub module: wgpu::ShaderModule,
+    source: String,
+    label: String,
+    // What the source was expanded from, to load it again when one of the
+    // files changes.
+    files: Vec<String>,
+    defines: Vec<(String, String)>,
+    layout: ShaderLayout,
+}
+
+impl Shader {
+    pub fn from_wgsl(gpu: &GPUHandle, source: &str, label: &str) -> Result<Self, GpuError> {
+        let module = gpu.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        })?;
+        Ok(Shader {
+            module,
+            source: source.to_string(),
+            label: label.to_string(),
+            files: Vec::new(),
+            defines: Vec::new(),
+            layout: ShaderLayout::default(),
+        })
+    }
+
+    // Compiles preprocessed source, validating it first so errors point at
+    // the files it came from.
+    pub fn from_source(gpu: &GPUHandle, source: &ShaderSource, label: &str) -> anyhow::Result<Self> {
+        let (module, info) = source.validate()?;
+        let layout = ShaderLayout::reflect(&module, &info)?;
+        let mut shader = Self::from_wgsl(gpu, &source.code, label)?;
+        shader.files = source.files.clone();
+        shader.layout = layout;
+        Ok(shader)
+    }
+
+    // Loads `path` through the asset system and preprocesses it with `defines`.
+    pub fn load(gpu: &GPUHandle, path: &str, defines: &[(&str, &str)]) -> anyhow::Result<Self> {
+        let source = ShaderSource::load(path, defines)?;
+        let mut shader = Self::from_source(gpu, &source, path)?;
+        shader.defines = defines.iter().map(|&(name, value)| (name.to_string(), value.to_string())).collect();
+        Ok(shader)
+    }
+
+    // Loads the shader again from its files, e.g. after one of them changed.
+    pub fn reload(&self, gpu: &GPUHandle) -> anyhow::Result<Self> {
+        let defines = self.defines.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect::<Vec<_>>();
+        Self::load(gpu, &self.label, &defines)
+    }
+
+    // Every file the shader was expanded from, its own first.
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    // The bind group layouts the shader declares. Empty for shaders created
+    // with `from_wgsl`, which aren't reflected.
+    pub fn layout(&self) -> &ShaderLayout {
+        &self.layout
+    }
+
+    // Compiles the same source again on a new device.
+    pub fn reupload(&self, gpu: &GPUHandle) -> Result<Self, GpuError> {
+        let mut shader = Self::from_wgsl(gpu, &self.source, &self.label)?;
+        shader.files = self.files.clone();
+        shader.defines = self.defines.clone();
+        shader.layout = self.layout.clone();
+        Ok(shader)
+    }
+}
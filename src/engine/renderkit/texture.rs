@@ -1,117 +1,169 @@
+use std::sync::Arc;
+
 use anyhow::*;
 
-use crate::engine::{resource::load_binary, renderkit::gpuhandle::GPUHandle};
+use crate::engine::renderkit::gpuerror;
 
+use super::texturefile::{TextureFile, TextureFileFormat};
 
 
+// The file a texture was created from, kept so it can be re-uploaded after
+// the device is lost.
+enum Source {
+    Encoded(Arc<[u8]>),
+    Cooked(Arc<[u8]>),
+}
 
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
+    source: Source,
+    label: String,
 }
 
 impl Texture {
 
-    pub async fn load_texture(
-        file_name: &str,
-        gpu: &GPUHandle,
-    ) -> anyhow::Result<Texture> {
-        let data = load_binary(file_name).await?;
-        Texture::from_bytes(&gpu.device, &gpu.queue, &data, file_name)
-    }
-
-    fn from_bytes(
+    pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         bytes: &[u8],
         label: &str,
     ) -> Result<Self> {
         let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+        Self::from_decoded(device, queue, Arc::from(bytes), &img, label)
     }
 
-    fn from_image(
+    // Uploads an image that was already decoded from `source`, e.g. on a
+    // loader thread.
+    pub fn from_decoded(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
+        source: Arc<[u8]>,
         img: &image::DynamicImage,
-        label: Option<&str>,
+        label: &str,
     ) -> Result<Self> {
-        let diffuse_rgba = img.to_rgba8();
-
-        use image::GenericImageView;
+        let rgba = img.to_rgba8();
+        let file = TextureFile {
+            format: TextureFileFormat::Rgba8,
+            width: rgba.width(),
+            height: rgba.height(),
+            levels: vec![rgba.as_raw().into()],
+        };
+        let (texture, view, sampler) = Self::upload(device, queue, &file, Some(label))?;
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+            source: Source::Encoded(source),
+            label: label.to_string(),
+        })
+    }
 
-        let dim = img.dimensions();
+    // Uploads a texture written by the asset cooker, with all of its mip
+    // levels. Block compressed textures are decompressed first if the device
+    // can't sample them.
+    pub fn from_cooked(device: &wgpu::Device, queue: &wgpu::Queue, source: Arc<[u8]>, label: &str) -> Result<Self> {
+        let mut file = TextureFile::parse(&source).with_context(|| format!("invalid cooked texture {}", label))?;
+        let max = device.limits().max_texture_dimension_2d;
+        if file.width > max || file.height > max {
+            bail!("texture {} is {}x{}, larger than the {} this device supports", label, file.width, file.height, max);
+        }
+        if file.format != TextureFileFormat::Rgba8 && !device.features().contains(wgpu::Features::TEXTURE_COMPRESSION_BC) {
+            file = file.decompress();
+        }
+        let (texture, view, sampler) = Self::upload(device, queue, &file, Some(label))?;
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+            source: Source::Cooked(source.clone()),
+            label: label.to_string(),
+        })
+    }
 
-        // lets create a texture to load our happy tree image
+    // Uploads the same image again on a new device.
+    pub fn reupload(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<Self> {
+        match &self.source {
+            Source::Encoded(bytes) => Self::from_bytes(device, queue, bytes, &self.label),
+            Source::Cooked(bytes) => Self::from_cooked(device, queue, bytes.clone(), &self.label),
+        }
+    }
 
+    fn upload(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        file: &TextureFile,
+        label: Option<&str>,
+    ) -> Result<(wgpu::Texture, wgpu::TextureView, wgpu::Sampler)> {
         let texture_size = wgpu::Extent3d {
-            width: dim.0,
-            height: dim.1,
+            width: file.width,
+            height: file.height,
             depth_or_array_layers: 1,
         };
 
         // make texture
-        let diffuse_texture = device.create_texture(
+        let diffuse_texture = gpuerror::scoped(device, "texture", label, || device.create_texture(
             &wgpu::TextureDescriptor{ 
-                label: Some("diffuse_texture_happytree :D"),
+                label,
                 // ALL textures are stored as 3D, we represent our 2d Texture
                 // bysetting it's deapth to 1.
                 size: texture_size,
-                mip_level_count: 1, // we'll tal;k about t his latr
+                mip_level_count: file.levels.len() as u32,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
                 // MOST images are stored as sRGB, let's tell the GPU that
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                format: file.format.wgpu_format(),
                 // TEXTURE_BINDING tells the GPU that we want to use this texture in shaders!
                 // COPY_DST means we wantto copy data to this texture
                 usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             }
-        );
-
-        // lets load our texture into the GPU
-        queue.write_texture(
-            wgpu::ImageCopyTexture{
-                texture: &diffuse_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            // pixeldata
-            &diffuse_rgba,
-            // Layout of texxture
-            wgpu::ImageDataLayout{
-                offset: 0,
-                bytes_per_row: std::num::NonZeroU32::new(4*dim.0),
-                rows_per_image: std::num::NonZeroU32::new(dim.1)
-            },
-            texture_size
-        );
+        ))?;
+
+        // lets load our texture into the GPU, one mip level at a time
+        for (level, data) in file.levels.iter().enumerate() {
+            let size = texture_size.mip_level_size(level as u32, false);
+            // Block compressed levels are copied in whole 4x4 blocks, even
+            // the ones smaller than a block.
+            let physical = size.physical_size(file.format.wgpu_format());
+            queue.write_texture(
+                wgpu::ImageCopyTexture{
+                    texture: &diffuse_texture,
+                    mip_level: level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                data,
+                wgpu::ImageDataLayout{
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(file.format.bytes_per_row(size.width)),
+                    rows_per_image: std::num::NonZeroU32::new(file.format.rows(size.height)),
+                },
+                physical,
+            );
+        }
+        let filter = if file.levels.len() > 1 { wgpu::FilterMode::Linear } else { wgpu::FilterMode::Nearest };
 
         let diffuse_texture_view = diffuse_texture.create_view(&wgpu::TextureViewDescriptor::default());
 
-        let diffuse_sampler = device.create_sampler(
+        let diffuse_sampler = gpuerror::scoped(device, "sampler", label, || device.create_sampler(
             &wgpu::SamplerDescriptor {
-                label: Some("E"),
+                label,
                 address_mode_u: wgpu::AddressMode::ClampToEdge,
                 address_mode_v: wgpu::AddressMode::ClampToEdge,
                 address_mode_w: wgpu::AddressMode::ClampToEdge,
 
                 mag_filter: wgpu::FilterMode::Linear,
-                min_filter: wgpu::FilterMode::Nearest,
-                mipmap_filter: wgpu::FilterMode::Nearest,
+                min_filter: filter,
+                mipmap_filter: filter,
                 ..Default::default()
             }
-        );
+        ))?;
 
 
 
  
 
-        Ok(Self {
-            texture: diffuse_texture,
-            view: diffuse_texture_view,
-            sampler: diffuse_sampler,
-        })
+        Ok((diffuse_texture, diffuse_texture_view, diffuse_sampler))
     }
 }
\ No newline at end of file
@@ -0,0 +1,236 @@
+use std::borrow::Cow;
+
+use anyhow::{bail, Context};
+
+use crate::engine::resource::bytereader::ByteReader;
+
+use super::bc::{self, BlockFormat};
+
+// Cooked textures are stored with every mip level ready to upload. Layout,
+// with every integer little endian:
+//
+//   magic "RMTX", version u32, format u8, 3 bytes padding, width u32,
+//   height u32, level count u32, then per level its size u64 and data
+const MAGIC: &[u8; 4] = b"RMTX";
+const VERSION: u32 = 1;
+// Far above what any device supports, to keep sizes from corrupt headers
+// sane. Devices check their own limit when the texture is uploaded.
+pub const MAX_DIMENSION: u32 = 1 << 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureFileFormat {
+    Rgba8,
+    Bc1,
+    Bc3,
+}
+
+impl TextureFileFormat {
+    fn from_byte(byte: u8) -> anyhow::Result<Self> {
+        Ok(match byte {
+            0 => TextureFileFormat::Rgba8,
+            1 => TextureFileFormat::Bc1,
+            2 => TextureFileFormat::Bc3,
+            _ => bail!("unknown texture format {}", byte),
+        })
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            TextureFileFormat::Rgba8 => 0,
+            TextureFileFormat::Bc1 => 1,
+            TextureFileFormat::Bc3 => 2,
+        }
+    }
+
+    fn block_format(self) -> Option<BlockFormat> {
+        match self {
+            TextureFileFormat::Rgba8 => None,
+            TextureFileFormat::Bc1 => Some(BlockFormat::Bc1),
+            TextureFileFormat::Bc3 => Some(BlockFormat::Bc3),
+        }
+    }
+
+    pub fn wgpu_format(self) -> wgpu::TextureFormat {
+        match self {
+            TextureFileFormat::Rgba8 => wgpu::TextureFormat::Rgba8UnormSrgb,
+            TextureFileFormat::Bc1 => wgpu::TextureFormat::Bc1RgbaUnormSrgb,
+            TextureFileFormat::Bc3 => wgpu::TextureFormat::Bc3RgbaUnormSrgb,
+        }
+    }
+
+    // Bytes per row of 4x4 blocks, or per row of pixels for RGBA.
+    pub fn bytes_per_row(self, width: u32) -> u32 {
+        match self.block_format() {
+            Some(block) => width.div_ceil(4) * block.block_size() as u32,
+            None => width * 4,
+        }
+    }
+
+    pub fn rows(self, height: u32) -> u32 {
+        match self.block_format() {
+            Some(_) => height.div_ceil(4),
+            None => height,
+        }
+    }
+}
+
+pub struct TextureFile<'a> {
+    pub format: TextureFileFormat,
+    pub width: u32,
+    pub height: u32,
+    // The full mip chain, largest first.
+    pub levels: Vec<Cow<'a, [u8]>>,
+}
+
+impl<'a> TextureFile<'a> {
+    pub fn parse(bytes: &'a [u8]) -> anyhow::Result<Self> {
+        let mut reader = ByteReader::new(bytes);
+        if reader.take(4)? != MAGIC {
+            bail!("not a cooked texture");
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            bail!("unsupported texture version {}, expected {}", version, VERSION);
+        }
+        let format = TextureFileFormat::from_byte(reader.take(4)?[0])?;
+        let width = reader.u32()?;
+        let height = reader.u32()?;
+        let level_count = reader.u32()?;
+        if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+            bail!("invalid texture size {}x{}", width, height);
+        }
+        // No more levels than it takes to get down to 1x1.
+        let max_levels = 32 - width.max(height).leading_zeros();
+        if level_count == 0 || level_count > max_levels {
+            bail!("invalid mip level count {} for a {}x{} texture", level_count, width, height);
+        }
+
+        let mut levels = Vec::new();
+        for level in 0..level_count {
+            let size = reader.u64()?;
+            let (w, h) = ((width >> level).max(1), (height >> level).max(1));
+            let expected = format.bytes_per_row(w) as u64 * format.rows(h) as u64;
+            if size != expected {
+                bail!("mip level {} has {} bytes, expected {}", level, size, expected);
+            }
+            let size = usize::try_from(size).context("mip level is too large")?;
+            levels.push(Cow::Borrowed(reader.take(size).context("truncated mip level")?));
+        }
+        Ok(TextureFile { format, width, height, levels })
+    }
+
+    // Builds the full mip chain of `image`, block compressed unless `format`
+    // is RGBA.
+    pub fn from_image(image: &image::RgbaImage, format: TextureFileFormat) -> Self {
+        let (width, height) = image.dimensions();
+        let level_count = 32 - width.max(height).leading_zeros();
+
+        let mut levels = Vec::new();
+        let mut level = image.clone();
+        for index in 0..level_count {
+            if index > 0 {
+                let (w, h) = ((width >> index).max(1), (height >> index).max(1));
+                level = image::imageops::resize(&level, w, h, image::imageops::FilterType::Triangle);
+            }
+            let data = match format.block_format() {
+                Some(block) => bc::encode(block, &level, level.width(), level.height()),
+                None => level.as_raw().clone(),
+            };
+            levels.push(Cow::Owned(data));
+        }
+        TextureFile { format, width, height, levels }
+    }
+
+    pub fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&[self.format.to_byte(), 0, 0, 0]);
+        out.extend_from_slice(&self.width.to_le_bytes());
+        out.extend_from_slice(&self.height.to_le_bytes());
+        out.extend_from_slice(&(self.levels.len() as u32).to_le_bytes());
+        for level in &self.levels {
+            out.extend_from_slice(&(level.len() as u64).to_le_bytes());
+            out.extend_from_slice(level);
+        }
+    }
+
+    // Converts block compressed levels to RGBA, for devices that can't
+    // sample them directly.
+    pub fn decompress(self) -> TextureFile<'static> {
+        let levels = self.levels.iter().enumerate()
+            .map(|(index, level)| {
+                let (w, h) = ((self.width >> index).max(1), (self.height >> index).max(1));
+                Cow::Owned(match self.format.block_format() {
+                    Some(block) => bc::decode(block, level, w, h),
+                    None => level.to_vec(),
+                })
+            })
+            .collect();
+        TextureFile {
+            format: TextureFileFormat::Rgba8,
+            width: self.width,
+            height: self.height,
+            levels,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(format: u8, width: u32, height: u32, level_count: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&[format, 0, 0, 0]);
+        bytes.extend_from_slice(&width.to_le_bytes());
+        bytes.extend_from_slice(&height.to_le_bytes());
+        bytes.extend_from_slice(&level_count.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let image = image::RgbaImage::from_fn(8, 4, |x, y| image::Rgba([x as u8 * 30, y as u8 * 60, 0, 255]));
+        for format in [TextureFileFormat::Rgba8, TextureFileFormat::Bc1, TextureFileFormat::Bc3] {
+            let mut bytes = Vec::new();
+            TextureFile::from_image(&image, format).write(&mut bytes);
+            let file = TextureFile::parse(&bytes).unwrap();
+            assert_eq!((file.format, file.width, file.height, file.levels.len()), (format, 8, 4, 4));
+            assert_eq!(file.levels[0].len() as u32, format.bytes_per_row(8) * format.rows(4));
+        }
+    }
+
+    #[test]
+    fn huge_dimensions_are_rejected() {
+        for (width, height) in [(u32::MAX, u32::MAX), (u32::MAX, 1), (1, MAX_DIMENSION + 1), (0, 4)] {
+            for format in 0..3 {
+                let mut bytes = header(format, width, height, 1);
+                bytes.extend_from_slice(&u64::MAX.to_le_bytes());
+                let error = TextureFile::parse(&bytes).err().unwrap();
+                assert!(error.to_string().contains("invalid texture size"), "{}", error);
+            }
+        }
+    }
+
+    #[test]
+    fn too_many_levels_are_rejected() {
+        assert!(TextureFile::parse(&header(0, 4, 4, 4)).is_err());
+        assert!(TextureFile::parse(&header(0, 4, 4, 0)).is_err());
+        assert!(TextureFile::parse(&header(0, 4, 4, u32::MAX)).is_err());
+    }
+
+    #[test]
+    fn wrong_level_sizes_are_rejected() {
+        let mut bytes = header(0, 2, 2, 1);
+        bytes.extend_from_slice(&15u64.to_le_bytes());
+        bytes.extend_from_slice(&[0; 15]);
+        assert!(TextureFile::parse(&bytes).is_err());
+
+        let mut truncated = header(0, 2, 2, 1);
+        truncated.extend_from_slice(&16u64.to_le_bytes());
+        truncated.extend_from_slice(&[0; 15]);
+        assert!(TextureFile::parse(&truncated).is_err());
+    }
+}
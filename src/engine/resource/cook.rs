@@ -0,0 +1,352 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+
+use crate::engine::renderkit::{
+    buffers::modelvertex::ModelVertex,
+    meshfile::MeshFile,
+    model::{MaterialData, MeshData, ObjData},
+    permutations::MaterialFeatures,
+    preprocessor::ShaderSource,
+    texturefile::{TextureFile, TextureFileFormat},
+};
+
+use super::list_assets;
+
+// Bumped whenever cooked output changes, so everything is cooked again.
+const COOK_VERSION: u32 = 3;
+// Remembers what each output was cooked from, inside the output directory.
+const CACHE_FILE: &str = ".cook-cache";
+
+// Cooked files are named after their source with one of these appended, e.g.
+// `models/cube.obj.mesh`. The loaders look for them before the source.
+pub const COOKED_MESH: &str = "mesh";
+pub const COOKED_TEXTURE: &str = "tex";
+
+// Shader snippets live in directories with this name. They're copied but not
+// validated on their own.
+const SHADER_INCLUDE_DIR: &str = "include";
+
+pub fn cooked_name(path: &str, extension: &str) -> String {
+    format!("{}.{}", path, extension)
+}
+
+fn extension(name: &str) -> String {
+    Path::new(name).extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase()
+}
+
+// Where the cooked version of the input `name` goes.
+fn output_name(name: &str) -> String {
+    match extension(name).as_str() {
+        "obj" | "gltf" | "glb" => cooked_name(name, COOKED_MESH),
+        "png" | "jpg" | "jpeg" => cooked_name(name, COOKED_TEXTURE),
+        _ => name.to_string(),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CookOptions {
+    // Block compress textures whose size is a multiple of 4.
+    pub compress_textures: bool,
+    // Cook everything, even inputs that haven't changed.
+    pub force: bool,
+}
+
+impl Default for CookOptions {
+    fn default() -> Self {
+        CookOptions {
+            compress_textures: true,
+            force: false,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct CookReport {
+    pub cooked: Vec<String>,
+    pub unchanged: usize,
+    // Inputs that were deleted since the last run, whose output was removed.
+    pub removed: Vec<String>,
+    pub failed: Vec<(String, anyhow::Error)>,
+}
+
+// What an input was cooked from last time: the hash of the input and every
+// file it pulled in, e.g. an OBJ's material library.
+struct CacheEntry {
+    hash: u32,
+    dependencies: Vec<PathBuf>,
+}
+
+// Converts every file under `input` into its runtime form under `output`:
+// OBJ and glTF models into cooked meshes, PNG and JPEG images into cooked
+// textures with mipmaps, and WGSL shaders are validated. Other files are
+// copied. Inputs whose contents haven't changed since the last run are
+// skipped, outputs of deleted inputs are removed, and a failing input doesn't
+// stop the others.
+pub fn cook_directory(input: &Path, output: &Path, options: &CookOptions) -> anyhow::Result<CookReport> {
+    std::fs::create_dir_all(output).with_context(|| format!("failed to create {}", output.display()))?;
+    let inputs = list_assets(input)?;
+    let mut cache = read_cache(&output.join(CACHE_FILE));
+
+    let mut report = CookReport::default();
+    let names = inputs.iter().map(|(name, _)| name.as_str()).collect::<HashSet<_>>();
+    let mut stale = cache.keys().filter(|name| !names.contains(name.as_str())).cloned().collect::<Vec<_>>();
+    stale.sort();
+    for name in stale {
+        let out = output.join(output_name(&name));
+        match std::fs::remove_file(&out) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => report.failed.push((name, anyhow::Error::new(e).context(format!("failed to remove {}", out.display())))),
+            _ => {
+                // Directories left empty go too, removing one that isn't fails.
+                let mut dir = out.parent();
+                while let Some(path) = dir.filter(|dir| *dir != output) {
+                    if std::fs::remove_dir(path).is_err() {
+                        break;
+                    }
+                    dir = path.parent();
+                }
+                cache.remove(&name);
+                report.removed.push(name);
+            }
+        }
+    }
+    if options.force {
+        cache.clear();
+    }
+
+    for (name, path) in inputs {
+        let out = output.join(output_name(&name));
+        if let Some(entry) = cache.get(&name) {
+            if out.is_file() && hash_inputs(&path, &entry.dependencies, options).ok() == Some(entry.hash) {
+                report.unchanged += 1;
+                continue;
+            }
+        }
+
+        match cook_file(input, &name, &path, options) {
+            Ok(cooked) => {
+                let result = out.parent()
+                    .map_or(Ok(()), std::fs::create_dir_all)
+                    .and_then(|_| replace_file(&out, &cooked.data))
+                    .with_context(|| format!("failed to write {}", out.display()))
+                    .and_then(|_| hash_inputs(&path, &cooked.dependencies, options));
+                match result {
+                    Ok(hash) => {
+                        cache.insert(name.clone(), CacheEntry { hash, dependencies: cooked.dependencies });
+                        report.cooked.push(name);
+                    }
+                    Err(e) => report.failed.push((name, e)),
+                }
+            }
+            Err(e) => {
+                cache.remove(&name);
+                report.failed.push((name, e));
+            }
+        }
+    }
+
+    write_cache(&output.join(CACHE_FILE), &cache)?;
+    Ok(report)
+}
+
+// Writes a new file and moves it over `path` rather than writing into it,
+// since a running engine may have the old one memory mapped.
+fn replace_file(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    std::fs::write(&temp, data)?;
+    std::fs::rename(&temp, path)
+}
+
+struct Cooked {
+    data: Vec<u8>,
+    dependencies: Vec<PathBuf>,
+}
+
+fn cook_file(input: &Path, name: &str, path: &Path, options: &CookOptions) -> anyhow::Result<Cooked> {
+    let mut dependencies = Vec::new();
+    let data = match extension(name).as_str() {
+        "obj" => {
+            let text = std::fs::read_to_string(path)?;
+            let libraries = RefCell::new(Vec::new());
+            let obj = ObjData::from_obj(&text, |mtl| {
+                let mtl = path.parent().unwrap_or(input).join(mtl);
+                let text = std::fs::read_to_string(&mtl).with_context(|| format!("failed to read {}", mtl.display()));
+                libraries.borrow_mut().push(mtl);
+                text
+            })?;
+            dependencies = libraries.into_inner();
+            write_mesh(&obj)
+        }
+        "gltf" | "glb" => {
+            let (obj, buffers) = import_gltf(name, path)?;
+            dependencies = buffers;
+            write_mesh(&obj)
+        }
+        "png" | "jpg" | "jpeg" => {
+            let image = image::open(path)?.to_rgba8();
+            let format = if !options.compress_textures || image.width() % 4 != 0 || image.height() % 4 != 0 {
+                TextureFileFormat::Rgba8
+            } else if image.pixels().any(|pixel| pixel[3] < 255) {
+                TextureFileFormat::Bc3
+            } else {
+                TextureFileFormat::Bc1
+            };
+            let mut data = Vec::new();
+            TextureFile::from_image(&image, format).write(&mut data);
+            data
+        }
+        "wgsl" => {
+            let source = std::fs::read_to_string(path)?;
+            // Snippets are validated as part of the shaders including them.
+            if !name.split('/').any(|part| part == SHADER_INCLUDE_DIR) {
+                let mut read = |include: &str| -> anyhow::Result<String> {
+                    let file = input.join(include);
+                    dependencies.push(file.clone());
+                    std::fs::read_to_string(&file).with_context(|| format!("failed to read {}", file.display()))
+                };
+                ShaderSource::preprocess(name, &source, &[], &mut read)?.validate()?;
+            }
+            source.into_bytes()
+        }
+        _ => std::fs::read(path)?,
+    };
+    Ok(Cooked { data, dependencies })
+}
+
+fn write_mesh(obj: &ObjData) -> Vec<u8> {
+    MeshFile::from_obj(obj).data().to_vec()
+}
+
+// Converts the triangle meshes of a glTF file. Texture paths are resolved
+// relative to the file, and only textures stored as separate files are
+// supported. Returns the external buffers read along the way.
+fn import_gltf(name: &str, path: &Path) -> anyhow::Result<(ObjData, Vec<PathBuf>)> {
+    let (document, buffers, _) = gltf::import(path)?;
+    let dir = Path::new(name).parent().unwrap_or(Path::new(""));
+    let asset_path = |uri: &str| dir.join(uri).to_string_lossy().replace('\\', "/");
+
+    let mut materials = document.materials()
+        .map(|material| {
+            let diffuse_texture = match material.pbr_metallic_roughness().base_color_texture() {
+                Some(info) => match info.texture().source().source() {
+                    gltf::image::Source::Uri { uri, .. } => asset_path(uri),
+                    gltf::image::Source::View { .. } => {
+                        bail!("material {:?} uses an embedded image", material.name().unwrap_or(""))
+                    }
+                },
+                None => String::new(),
+            };
+            let mut features = MaterialFeatures::NONE;
+            if material.normal_texture().is_some() {
+                features |= MaterialFeatures::NORMAL_MAP;
+            }
+            if material.emissive_texture().is_some() {
+                features |= MaterialFeatures::EMISSIVE;
+            }
+            if material.alpha_mode() == gltf::material::AlphaMode::Mask {
+                features |= MaterialFeatures::ALPHA_TEST;
+            }
+            Ok(MaterialData {
+                name: material.name().unwrap_or("").to_string(),
+                diffuse_texture,
+                features,
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    // Primitives without a material use glTF's default one, added at the end.
+    let default_material = materials.len();
+
+    let mut meshes = Vec::new();
+    for mesh in document.meshes() {
+        for (index, primitive) in mesh.primitives().enumerate() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                bail!("mesh {:?} has {:?} primitives, only triangles are supported", mesh.name(), primitive.mode());
+            }
+            let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data.0[..]));
+            let positions = reader.read_positions().context("primitive has no positions")?.collect::<Vec<_>>();
+            let mut normals = reader.read_normals().map(|normals| normals.collect::<Vec<_>>()).unwrap_or_default();
+            normals.resize(positions.len(), [0.0, 0.0, 1.0]);
+            let mut tex_coords = reader.read_tex_coords(0)
+                .map(|tex_coords| tex_coords.into_f32().collect::<Vec<_>>())
+                .unwrap_or_default();
+            tex_coords.resize(positions.len(), [0.0, 0.0]);
+            let indices = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+
+            meshes.push(MeshData {
+                name: format!("{}.{}", mesh.name().unwrap_or("mesh"), index),
+                vertices: positions.iter().zip(&tex_coords).zip(&normals)
+                    .map(|((&position, &tex_coords), &normal)| ModelVertex { position, tex_coords, normal })
+                    .collect(),
+                indices,
+                material: primitive.material().index().unwrap_or(default_material),
+            });
+        }
+    }
+    if meshes.iter().any(|mesh| mesh.material == default_material) {
+        materials.push(MaterialData {
+            name: String::from("default"),
+            diffuse_texture: String::new(),
+            features: MaterialFeatures::NONE,
+        });
+    }
+
+    let base = path.parent().unwrap_or(Path::new(""));
+    let dependencies = document.buffers()
+        .filter_map(|buffer| match buffer.source() {
+            gltf::buffer::Source::Uri(uri) if !uri.starts_with("data:") => Some(base.join(uri)),
+            _ => None,
+        })
+        .collect();
+    Ok((ObjData { meshes, materials }, dependencies))
+}
+
+// A CRC32 rather than `DefaultHasher`, whose output may change between Rust
+// releases and make the cache useless.
+fn hash_inputs(path: &Path, dependencies: &[PathBuf], options: &CookOptions) -> anyhow::Result<u32> {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&COOK_VERSION.to_le_bytes());
+    hasher.update(&[options.compress_textures as u8]);
+    for file in std::iter::once(path).chain(dependencies.iter().map(PathBuf::as_path)) {
+        let data = std::fs::read(file).with_context(|| format!("failed to read {}", file.display()))?;
+        hasher.update(&(data.len() as u64).to_le_bytes());
+        hasher.update(&data);
+    }
+    Ok(hasher.finalize())
+}
+
+// One line per input: its asset path, the hash and the dependencies, separated
+// by tabs. A missing or unreadable cache just means cooking everything.
+fn read_cache(path: &Path) -> HashMap<String, CacheEntry> {
+    let text = std::fs::read_to_string(path).unwrap_or_default();
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let name = fields.next()?.to_string();
+            let hash = u32::from_str_radix(fields.next()?, 16).ok()?;
+            let dependencies = fields.map(PathBuf::from).collect();
+            Some((name, CacheEntry { hash, dependencies }))
+        })
+        .collect()
+}
+
+fn write_cache(path: &Path, cache: &HashMap<String, CacheEntry>) -> anyhow::Result<()> {
+    let mut names = cache.keys().collect::<Vec<_>>();
+    names.sort();
+    let mut text = String::new();
+    for name in names {
+        let entry = &cache[name];
+        text.push_str(&format!("{}\t{:08x}", name, entry.hash));
+        for dependency in &entry.dependencies {
+            text.push('\t');
+            text.push_str(&dependency.to_string_lossy());
+        }
+        text.push('\n');
+    }
+    std::fs::write(path, text).with_context(|| format!("failed to write {}", path.display()))
+}
@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+
+use super::{vfs::Mount, SharedBytes};
+
+// Every file in res/ at build time, sorted by name. Empty unless built with
+// the `embed-assets` feature.
+static ASSETS: &[(&str, &[u8])] = include!(concat!(env!("OUT_DIR"), "/embedded_assets.rs"));
+
+pub fn get(path: &str) -> Option<&'static [u8]> {
+    let index = ASSETS.binary_search_by(|(name, _)| (*name).cmp(path)).ok()?;
+    Some(ASSETS[index].1)
+}
+
+pub fn paths() -> impl Iterator<Item = &'static str> {
+    ASSETS.iter().map(|(name, _)| *name)
+}
+
+// Assets compiled into the binary, mounted below everything on disk so a
+// single executable runs without any files next to it.
+pub struct EmbeddedMount;
+
+impl Mount for EmbeddedMount {
+    fn contains(&self, path: &str) -> bool {
+        get(path).is_some()
+    }
+
+    fn read(&self, path: &str) -> anyhow::Result<Vec<u8>> {
+        get(path).map(<[u8]>::to_vec).with_context(|| format!("{} isn't embedded", path))
+    }
+
+    fn map(&self, path: &str) -> anyhow::Result<SharedBytes> {
+        Ok(Arc::new(get(path).with_context(|| format!("{} isn't embedded", path))?))
+    }
+
+    fn describe(&self) -> String {
+        format!("<embedded, {} files>", ASSETS.len())
+    }
+}
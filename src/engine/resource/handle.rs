@@ -0,0 +1,117 @@
+use std::ops::Deref;
+use std::sync::{Arc, RwLock};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoadState {
+    // Still being read or decoded, the handle points at a placeholder.
+    Loading,
+    Loaded,
+    // Loading failed with this error, the handle keeps pointing at a placeholder.
+    Failed(String),
+}
+
+// Shared by every handle to one asset. The asset itself is swapped out when
+// it finishes loading or is reloaded, and dropped together with the slot once
+// the last handle goes.
+pub(super) struct AssetSlot<T> {
+    pub(super) path: String,
+    pub(super) asset: RwLock<Arc<T>>,
+    pub(super) state: RwLock<LoadState>,
+}
+
+impl<T> AssetSlot<T> {
+    pub(super) fn current(&self) -> Arc<T> {
+        self.asset.read().unwrap().clone()
+    }
+
+    pub(super) fn state(&self) -> LoadState {
+        self.state.read().unwrap().clone()
+    }
+
+    pub(super) fn replace(&self, asset: Arc<T>) {
+        *self.asset.write().unwrap() = asset;
+    }
+
+    pub(super) fn finish(&self, asset: T) {
+        self.replace(Arc::new(asset));
+        *self.state.write().unwrap() = LoadState::Loaded;
+    }
+
+    pub(super) fn fail(&self, error: &anyhow::Error) {
+        log::error!("failed to load {}: {:?}", self.path, error);
+        *self.state.write().unwrap() = LoadState::Failed(format!("{:#}", error));
+    }
+}
+
+impl<T> Drop for AssetSlot<T> {
+    fn drop(&mut self) {
+        log::debug!("unloading {}", self.path);
+    }
+}
+
+// A reference counted handle to an asset owned by the `AssetServer`. Handles
+// dereference to the version of the asset they last saw, call `refresh` to
+// pick up a newer one.
+pub struct Handle<T> {
+    slot: Arc<AssetSlot<T>>,
+    current: Arc<T>,
+}
+
+impl<T> Handle<T> {
+    pub(super) fn new(slot: Arc<AssetSlot<T>>) -> Self {
+        let current = slot.current();
+        Handle { slot, current }
+    }
+
+    pub(super) fn slot(&self) -> &Arc<AssetSlot<T>> {
+        &self.slot
+    }
+
+    pub fn path(&self) -> &str {
+        &self.slot.path
+    }
+
+    pub fn state(&self) -> LoadState {
+        self.slot.state()
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.state() == LoadState::Loaded
+    }
+
+    // Switches to the latest version of the asset, returning whether it changed.
+    pub fn refresh(&mut self) -> bool {
+        let latest = self.slot.current();
+        if Arc::ptr_eq(&latest, &self.current) {
+            return false;
+        }
+        self.current = latest;
+        true
+    }
+
+    // How many handles to this asset exist, including this one.
+    pub fn ref_count(&self) -> usize {
+        Arc::strong_count(&self.slot)
+    }
+
+    pub fn ptr_eq(&self, other: &Handle<T>) -> bool {
+        Arc::ptr_eq(&self.slot, &other.slot)
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Handle {
+            slot: self.slot.clone(),
+            current: self.current.clone(),
+        }
+    }
+}
+
+impl<T> Deref for Handle<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.current
+    }
+}
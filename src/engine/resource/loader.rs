@@ -0,0 +1,97 @@
+use std::panic::AssertUnwindSafe;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// A fixed pool of threads for the CPU side of loading: file reads, image
+// decoding and mesh parsing. Anything touching the GPU stays on the main thread.
+pub(super) struct Loader {
+    jobs: Option<mpsc::Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Loader {
+    pub(super) fn new() -> Self {
+        // Leave a core for the main thread.
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get().saturating_sub(1)).clamp(1, 4);
+
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..threads)
+            .map(|i| {
+                let receiver = receiver.clone();
+                std::thread::Builder::new()
+                    .name(format!("asset-loader-{}", i))
+                    .spawn(move || loop {
+                        // The lock is released before the job runs.
+                        let job = receiver.lock().unwrap().recv();
+                        match job {
+                            // A panicking job doesn't take the thread with it.
+                            Ok(job) => {
+                                if let Err(panic) = std::panic::catch_unwind(AssertUnwindSafe(job)) {
+                                    log::error!("asset loader job panicked: {}", panic_message(&*panic));
+                                }
+                            }
+                            Err(_) => break,
+                        }
+                    })
+                    .expect("failed to spawn asset loader thread")
+            })
+            .collect();
+
+        Loader {
+            jobs: Some(sender),
+            workers,
+        }
+    }
+
+    pub(super) fn spawn(&self, job: impl FnOnce() + Send + 'static) {
+        if let Some(jobs) = &self.jobs {
+            jobs.send(Box::new(job)).ok();
+        }
+    }
+}
+
+// The message `panic!` was called with, if it was given one.
+pub(super) fn panic_message(panic: &(dyn std::any::Any + Send)) -> &str {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => panic.downcast_ref::<String>().map_or("unknown panic", String::as_str),
+    }
+}
+
+impl Drop for Loader {
+    fn drop(&mut self) {
+        // Closing the channel stops the workers once they finish their job.
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            worker.join().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn workers_survive_panicking_jobs() {
+        let loader = Loader::new();
+        for _ in 0..loader.workers.len() * 2 {
+            loader.spawn(|| panic!("job failed"));
+        }
+        let (sender, receiver) = mpsc::channel();
+        for i in 0..loader.workers.len() {
+            let sender = sender.clone();
+            loader.spawn(move || sender.send(i).unwrap());
+        }
+        let mut done = (0..loader.workers.len())
+            .map(|_| receiver.recv_timeout(Duration::from_secs(10)).unwrap())
+            .collect::<Vec<_>>();
+        done.sort();
+        assert_eq!(done, (0..loader.workers.len()).collect::<Vec<_>>());
+    }
+}
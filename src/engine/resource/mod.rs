@@ -0,0 +1,68 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Context;
+
+pub(crate) mod bytereader;
+pub mod cook;
+pub mod embedded;
+mod handle;
+mod loader;
+pub mod pack;
+mod roots;
+mod server;
+pub mod vfs;
+mod watcher;
+
+pub use handle::{Handle, LoadState};
+pub use roots::{default_asset_roots, set_asset_roots, ASSET_PATH_VAR};
+pub use server::AssetServer;
+
+// Bytes shared without copying, e.g. a memory mapped file.
+pub type SharedBytes = Arc<dyn AsRef<[u8]> + Send + Sync>;
+
+pub fn load_string(file_name: &str) -> anyhow::Result<String> {
+    let txt = String::from_utf8(vfs::vfs().read(file_name)?)?;
+    Ok(txt)
+}
+
+pub fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
+    let data = vfs::vfs().read(file_name)?;
+
+    Ok(data)
+}
+
+pub fn map_binary(file_name: &str) -> anyhow::Result<SharedBytes> {
+    vfs::vfs().map(file_name)
+}
+
+// Every file under `dir` with its asset path, i.e. its `/` separated path
+// relative to `dir`, sorted by asset path.
+pub fn list_assets(dir: &Path) -> anyhow::Result<Vec<(String, PathBuf)>> {
+    let mut files = Vec::new();
+    collect_files(dir, &mut files).with_context(|| format!("failed to list {}", dir.display()))?;
+    let mut assets = files.into_iter()
+        .map(|file| {
+            let name = file.strip_prefix(dir)?
+                .components()
+                .map(|component| component.as_os_str().to_str().context("file name isn't UTF-8"))
+                .collect::<anyhow::Result<Vec<_>>>()?
+                .join("/");
+            Ok((name, file))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    assets.sort();
+    Ok(assets)
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
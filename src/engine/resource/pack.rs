@@ -0,0 +1,407 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{bail, Context};
+
+use super::list_assets;
+
+// A pack archive bundles many assets into one file. Layout, with every
+// integer little endian:
+//
+//   header  magic "RMPK", version u32, entry count u32, TOC offset u64
+//   data    the stored bytes of every file, back to back
+//   TOC     per file: path length u16, path (UTF-8, `/` separated), offset u64,
+//           stored size u64, size u64, compression u8, CRC32 of the contents u32
+const MAGIC: &[u8; 4] = b"RMPK";
+const VERSION: u32 = 1;
+const HEADER_SIZE: u64 = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Deflate,
+}
+
+impl Compression {
+    fn from_byte(byte: u8) -> anyhow::Result<Self> {
+        Ok(match byte {
+            0 => Compression::None,
+            1 => Compression::Deflate,
+            _ => bail!("unknown compression {}", byte),
+        })
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Deflate => 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    offset: u64,
+    stored_size: u64,
+    size: u64,
+    compression: Compression,
+    checksum: u32,
+}
+
+// An open pack archive. Only the table of contents is read up front, files
+// are read, decompressed and checked on demand.
+pub struct PackReader {
+    path: PathBuf,
+    file: Mutex<File>,
+    entries: HashMap<String, Entry>,
+}
+
+impl PackReader {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("failed to open pack {}", path.display()))?;
+        let entries = Self::read_toc(&file).with_context(|| format!("invalid pack {}", path.display()))?;
+        Ok(PackReader {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            entries,
+        })
+    }
+
+    fn read_toc(file: &File) -> anyhow::Result<HashMap<String, Entry>> {
+        let file_size = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic).context("truncated header")?;
+        if &magic != MAGIC {
+            bail!("not a pack archive");
+        }
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            bail!("unsupported version {}, expected {}", version, VERSION);
+        }
+        let count = read_u32(&mut reader)?;
+        let toc_offset = read_u64(&mut reader)?;
+        if toc_offset < HEADER_SIZE || toc_offset > file_size {
+            bail!("table of contents at {} is outside the file", toc_offset);
+        }
+
+        reader.seek(SeekFrom::Start(toc_offset))?;
+        let mut entries = HashMap::new();
+        for _ in 0..count {
+            let mut path = vec![0; read_u16(&mut reader)? as usize];
+            reader.read_exact(&mut path).context("truncated table of contents")?;
+            let path = String::from_utf8(path).context("file name isn't UTF-8")?;
+            let entry = Entry {
+                offset: read_u64(&mut reader)?,
+                stored_size: read_u64(&mut reader)?,
+                size: read_u64(&mut reader)?,
+                compression: Compression::from_byte(read_u8(&mut reader)?)
+                    .with_context(|| format!("invalid entry {}", path))?,
+                checksum: read_u32(&mut reader)?,
+            };
+            if entry.offset < HEADER_SIZE || entry.offset.saturating_add(entry.stored_size) > toc_offset {
+                bail!("entry {} points outside the data section", path);
+            }
+            entries.insert(path, entry);
+        }
+        Ok(entries)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.entries.contains_key(path)
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    pub fn read(&self, path: &str) -> anyhow::Result<Vec<u8>> {
+        let entry = self.entries.get(path)
+            .with_context(|| format!("{} isn't in pack {}", path, self.path.display()))?;
+
+        let mut stored = vec![0; entry.stored_size as usize];
+        {
+            let mut file = self.file.lock().unwrap();
+            file.seek(SeekFrom::Start(entry.offset))?;
+            file.read_exact(&mut stored)?;
+        }
+
+        let data = match entry.compression {
+            Compression::None => stored,
+            Compression::Deflate => {
+                // The TOC isn't trusted until the checksum is, so the output
+                // is capped rather than reserved up front.
+                let mut data = Vec::new();
+                flate2::read::DeflateDecoder::new(stored.as_slice())
+                    .take(entry.size.saturating_add(1))
+                    .read_to_end(&mut data)
+                    .with_context(|| format!("failed to decompress {} in pack {}", path, self.path.display()))?;
+                if data.len() as u64 > entry.size {
+                    bail!("{} in pack {} is corrupt, it decompresses to more than {} bytes", path, self.path.display(), entry.size);
+                }
+                data
+            }
+        };
+        if data.len() as u64 != entry.size || crc32fast::hash(&data) != entry.checksum {
+            bail!("{} in pack {} is corrupt, its checksum doesn't match", path, self.path.display());
+        }
+        Ok(data)
+    }
+}
+
+// Writes a pack archive. Files are streamed to disk as they're added and the
+// table of contents is written by `finish`.
+pub struct PackWriter {
+    file: BufWriter<File>,
+    entries: Vec<(String, Entry)>,
+    offset: u64,
+}
+
+impl PackWriter {
+    pub fn create(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let mut file = BufWriter::new(
+            File::create(path).with_context(|| format!("failed to create pack {}", path.display()))?,
+        );
+        // Written again with the real values once the TOC offset is known.
+        file.write_all(&[0; HEADER_SIZE as usize])?;
+        Ok(PackWriter {
+            file,
+            entries: Vec::new(),
+            offset: HEADER_SIZE,
+        })
+    }
+
+    // Adds `data` under `path`. Compressed files that don't get any smaller,
+    // e.g. PNGs, are stored as they are.
+    pub fn add(&mut self, path: &str, data: &[u8], compression: Compression) -> anyhow::Result<()> {
+        if path.len() > u16::MAX as usize {
+            bail!("file name {} is too long", path);
+        }
+        let compressed = match compression {
+            Compression::None => None,
+            Compression::Deflate => {
+                let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(data)?;
+                Some(encoder.finish()?).filter(|compressed| compressed.len() < data.len())
+            }
+        };
+        let (stored, compression) = match &compressed {
+            Some(compressed) => (compressed.as_slice(), Compression::Deflate),
+            None => (data, Compression::None),
+        };
+
+        self.file.write_all(stored)?;
+        self.entries.push((path.to_string(), Entry {
+            offset: self.offset,
+            stored_size: stored.len() as u64,
+            size: data.len() as u64,
+            compression,
+            checksum: crc32fast::hash(data),
+        }));
+        self.offset += stored.len() as u64;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        let toc_offset = self.offset;
+        for (path, entry) in &self.entries {
+            self.file.write_all(&(path.len() as u16).to_le_bytes())?;
+            self.file.write_all(path.as_bytes())?;
+            self.file.write_all(&entry.offset.to_le_bytes())?;
+            self.file.write_all(&entry.stored_size.to_le_bytes())?;
+            self.file.write_all(&entry.size.to_le_bytes())?;
+            self.file.write_all(&[entry.compression.to_byte()])?;
+            self.file.write_all(&entry.checksum.to_le_bytes())?;
+        }
+
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(MAGIC)?;
+        self.file.write_all(&VERSION.to_le_bytes())?;
+        self.file.write_all(&(self.entries.len() as u32).to_le_bytes())?;
+        self.file.write_all(&toc_offset.to_le_bytes())?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+// Packs every file under `dir` into `out`, named by their asset path
+// relative to `dir`. Returns how many files were packed.
+pub fn pack_directory(dir: &Path, out: &Path, compression: Compression) -> anyhow::Result<usize> {
+    let mut files = list_assets(dir)?;
+    // Don't pack an older version of the archive itself.
+    if let Ok(out) = out.canonicalize() {
+        files.retain(|(_, file)| file.canonicalize().ok().as_ref() != Some(&out));
+    }
+
+    let mut writer = PackWriter::create(out)?;
+    for (name, file) in &files {
+        let data = std::fs::read(file).with_context(|| format!("failed to read {}", file.display()))?;
+        writer.add(name, &data, compression)?;
+    }
+    writer.finish()?;
+    Ok(files.len())
+}
+
+fn read_u8(reader: &mut impl Read) -> anyhow::Result<u8> {
+    let mut bytes = [0; 1];
+    reader.read_exact(&mut bytes).context("truncated pack")?;
+    Ok(bytes[0])
+}
+
+fn read_u16(reader: &mut impl Read) -> anyhow::Result<u16> {
+    let mut bytes = [0; 2];
+    reader.read_exact(&mut bytes).context("truncated pack")?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn read_u32(reader: &mut impl Read) -> anyhow::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes).context("truncated pack")?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> anyhow::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes).context("truncated pack")?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A pack file in the temp directory, removed when dropped.
+    struct TempPack(PathBuf);
+
+    impl TempPack {
+        fn new(name: &str) -> TempPack {
+            TempPack(std::env::temp_dir().join(format!("rmagic-{}-{}.pak", std::process::id(), name)))
+        }
+
+        fn bytes(&self) -> Vec<u8> {
+            std::fs::read(&self.0).unwrap()
+        }
+
+        fn set_bytes(&self, bytes: &[u8]) {
+            std::fs::write(&self.0, bytes).unwrap();
+        }
+    }
+
+    impl Drop for TempPack {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    const TEXT: &[u8] = b"compressible compressible compressible compressible compressible";
+
+    fn write_pack(pack: &TempPack, compression: Compression) {
+        let mut writer = PackWriter::create(&pack.0).unwrap();
+        writer.add("text.txt", TEXT, compression).unwrap();
+        writer.add("dir/bytes.bin", &[7, 1, 200], compression).unwrap();
+        writer.add("empty", &[], compression).unwrap();
+        writer.finish().unwrap();
+    }
+
+    // Where the TOC entry of the first file added in `write_pack` starts.
+    fn first_entry(bytes: &[u8]) -> usize {
+        u64::from_le_bytes(bytes[12..20].try_into().unwrap()) as usize + 2 + "text.txt".len()
+    }
+
+    #[test]
+    fn round_trip() {
+        for compression in [Compression::None, Compression::Deflate] {
+            let pack = TempPack::new(&format!("round-trip-{:?}", compression));
+            write_pack(&pack, compression);
+            let reader = PackReader::open(&pack.0).unwrap();
+            let mut paths = reader.paths().collect::<Vec<_>>();
+            paths.sort();
+            assert_eq!(paths, ["dir/bytes.bin", "empty", "text.txt"]);
+            assert_eq!(reader.read("text.txt").unwrap(), TEXT);
+            assert_eq!(reader.read("dir/bytes.bin").unwrap(), [7, 1, 200]);
+            assert!(reader.read("empty").unwrap().is_empty());
+            assert!(!reader.contains("missing"));
+            assert!(reader.read("missing").is_err());
+        }
+    }
+
+    #[test]
+    fn incompressible_files_are_stored() {
+        let pack = TempPack::new("stored");
+        write_pack(&pack, Compression::Deflate);
+        let reader = PackReader::open(&pack.0).unwrap();
+        assert_eq!(reader.entries["text.txt"].compression, Compression::Deflate);
+        assert!(reader.entries["text.txt"].stored_size < TEXT.len() as u64);
+        assert_eq!(reader.entries["dir/bytes.bin"].compression, Compression::None);
+    }
+
+    #[test]
+    fn corrupt_data_fails_the_checksum() {
+        for compression in [Compression::None, Compression::Deflate] {
+            let pack = TempPack::new(&format!("corrupt-{:?}", compression));
+            write_pack(&pack, compression);
+            let mut bytes = pack.bytes();
+            bytes[HEADER_SIZE as usize + 1] ^= 0x55;
+            pack.set_bytes(&bytes);
+            let reader = PackReader::open(&pack.0).unwrap();
+            assert!(reader.read("text.txt").is_err());
+            assert_eq!(reader.read("dir/bytes.bin").unwrap(), [7, 1, 200]);
+        }
+    }
+
+    #[test]
+    fn decompression_stops_at_the_stored_size() {
+        let pack = TempPack::new("size");
+        write_pack(&pack, Compression::Deflate);
+        let mut bytes = pack.bytes();
+        // The size follows the offset and stored size.
+        let size = first_entry(&bytes) + 16;
+        bytes[size..size + 8].copy_from_slice(&4u64.to_le_bytes());
+        pack.set_bytes(&bytes);
+        let error = PackReader::open(&pack.0).unwrap().read("text.txt").unwrap_err();
+        assert!(error.to_string().contains("more than 4 bytes"), "{}", error);
+    }
+
+    #[test]
+    fn invalid_tables_of_contents_are_rejected() {
+        let pack = TempPack::new("toc");
+        write_pack(&pack, Compression::None);
+        let bytes = pack.bytes();
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        let mut bad_version = bytes.clone();
+        bad_version[4] = 99;
+        let mut toc_outside = bytes.clone();
+        toc_outside[12..20].copy_from_slice(&(bytes.len() as u64 + 1).to_le_bytes());
+        let mut entry_outside = bytes.clone();
+        let offset = first_entry(&bytes);
+        entry_outside[offset..offset + 8].copy_from_slice(&(bytes.len() as u64).to_le_bytes());
+        let mut bad_compression = bytes.clone();
+        bad_compression[offset + 24] = 9;
+        let truncated = bytes[..bytes.len() - 1].to_vec();
+
+        for (name, bytes) in [
+            ("bad magic", bad_magic),
+            ("bad version", bad_version),
+            ("TOC outside the file", toc_outside),
+            ("entry outside the data", entry_outside),
+            ("unknown compression", bad_compression),
+            ("truncated", truncated),
+            ("empty", Vec::new()),
+        ] {
+            pack.set_bytes(&bytes);
+            assert!(PackReader::open(&pack.0).is_err(), "{} was accepted", name);
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use std::path::{Path, PathBuf};
+
+use super::vfs::{set_vfs, Vfs};
+
+// Environment variable with extra asset directories, separated like `PATH`.
+pub const ASSET_PATH_VAR: &str = "RMAGIC_ASSET_PATH";
+
+// The roots to search when nothing else is set up: `RMAGIC_ASSET_PATH`, then
+// `configured`, then `res/` next to the executable, then `res/` in the working
+// directory so `cargo run` from the source tree works without copying.
+pub fn default_asset_roots(configured: &[PathBuf]) -> Vec<PathBuf> {
+    let mut roots = Vec::new();
+    if let Some(paths) = std::env::var_os(ASSET_PATH_VAR) {
+        roots.extend(std::env::split_paths(&paths).filter(|path| !path.as_os_str().is_empty()));
+    }
+    roots.extend(configured.iter().cloned());
+    if let Some(dir) = std::env::current_exe().ok().as_deref().and_then(Path::parent) {
+        roots.push(dir.join("res"));
+    }
+    roots.push(PathBuf::from("res"));
+    // Tests run from wherever cargo puts them.
+    #[cfg(test)]
+    roots.push(Path::new(env!("CARGO_MANIFEST_DIR")).join("res"));
+    roots
+}
+
+// Replaces every mount with `roots`, searched in order so earlier roots
+// override files in later ones.
+pub fn set_asset_roots(roots: &[PathBuf]) {
+    set_vfs(Vfs::from_roots(roots));
+}
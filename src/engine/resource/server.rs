@@ -0,0 +1,503 @@
+use std::collections::{HashMap, VecDeque};
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, RwLock, Weak};
+use std::time::{Duration, Instant};
+
+use crate::engine::renderkit::{
+    bindgroups::BindGroups,
+    gpuhandle::GPUHandle,
+    meshfile::MeshFile,
+    model::{Model, ObjData},
+    preprocessor::ShaderSource,
+    shader::Shader,
+    texture::Texture,
+};
+
+use super::{
+    cook::{cooked_name, COOKED_MESH, COOKED_TEXTURE},
+    handle::{AssetSlot, Handle, LoadState},
+    load_binary, load_string, map_binary,
+    loader::{panic_message, Loader},
+    vfs::vfs,
+    watcher::FileWatcher,
+};
+
+// Assets of one type. The store only holds weak references, so an asset is
+// unloaded as soon as the last handle to it is dropped.
+struct AssetStore<T> {
+    by_path: HashMap<String, Weak<AssetSlot<T>>>,
+    // Assets created from memory rather than loaded from a path, which are
+    // never deduplicated but still need restoring after a device loss.
+    added: Vec<Weak<AssetSlot<T>>>,
+}
+
+impl<T> AssetStore<T> {
+    fn new() -> Self {
+        AssetStore {
+            by_path: HashMap::new(),
+            added: Vec::new(),
+        }
+    }
+
+    fn get(&self, path: &str) -> Option<Handle<T>> {
+        self.by_path.get(path)?.upgrade().map(Handle::new)
+    }
+
+    fn slot(&self, path: &str) -> Option<Arc<AssetSlot<T>>> {
+        self.by_path.get(path)?.upgrade()
+    }
+
+    fn insert(&mut self, path: &str, asset: Arc<T>, state: LoadState) -> Handle<T> {
+        let slot = Self::new_slot(path, asset, state);
+        self.by_path.insert(path.to_string(), Arc::downgrade(&slot));
+        Handle::new(slot)
+    }
+
+    fn add(&mut self, name: &str, asset: T) -> Handle<T> {
+        let slot = Self::new_slot(name, Arc::new(asset), LoadState::Loaded);
+        self.added.push(Arc::downgrade(&slot));
+        Handle::new(slot)
+    }
+
+    fn new_slot(path: &str, asset: Arc<T>, state: LoadState) -> Arc<AssetSlot<T>> {
+        Arc::new(AssetSlot {
+            path: path.to_string(),
+            asset: RwLock::new(asset),
+            state: RwLock::new(state),
+        })
+    }
+
+    // Every asset that still has handles, forgetting the ones that don't.
+    fn live(&mut self) -> Vec<Arc<AssetSlot<T>>> {
+        self.by_path.retain(|_, slot| slot.strong_count() > 0);
+        self.added.retain(|slot| slot.strong_count() > 0);
+        self.by_path.values().chain(&self.added).filter_map(Weak::upgrade).collect()
+    }
+}
+
+// CPU side results sent back from the loader threads.
+enum Decoded {
+    Texture(Arc<[u8]>, image::DynamicImage),
+    // Written by the asset cooker and uploaded as it is.
+    CookedTexture(Arc<[u8]>),
+    Model(MeshFile),
+}
+
+struct Completed {
+    path: String,
+    result: anyhow::Result<Decoded>,
+}
+
+// The cooked version of `path` if there is one, otherwise `path` itself.
+fn source_name(path: &str, cooked_extension: &str) -> String {
+    let cooked = cooked_name(path, cooked_extension);
+    if vfs().contains(&cooked) {
+        return cooked;
+    }
+    path.to_string()
+}
+
+fn decode_texture(path: &str) -> anyhow::Result<Decoded> {
+    let source = source_name(path, COOKED_TEXTURE);
+    let bytes: Arc<[u8]> = load_binary(&source)?.into();
+    if source != path {
+        return Ok(Decoded::CookedTexture(bytes));
+    }
+    let image = image::load_from_memory(&bytes)?;
+    Ok(Decoded::Texture(bytes, image))
+}
+
+fn decode_model(path: &str) -> anyhow::Result<Decoded> {
+    let source = source_name(path, COOKED_MESH);
+    if source != path {
+        return Ok(Decoded::Model(MeshFile::parse(map_binary(&source)?)?));
+    }
+    Ok(Decoded::Model(MeshFile::from_obj(&ObjData::load(path)?)))
+}
+
+// Decodes `path` and sends the result back. A decoder that panics fails the
+// load rather than leaving it loading forever.
+fn decode_job(
+    path: &str,
+    decode: impl FnOnce(&str) -> anyhow::Result<Decoded> + Send + 'static,
+    sender: mpsc::Sender<Completed>,
+) -> impl FnOnce() + Send + 'static {
+    let path = path.to_string();
+    move || {
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| decode(&path)))
+            .unwrap_or_else(|panic| Err(anyhow::anyhow!("loading {} panicked: {}", path, panic_message(&*panic))));
+        sender.send(Completed { path, result }).ok();
+    }
+}
+
+// What to reload when a watched file changes. Textures and models are found
+// by their asset path, shaders by their slot since a file may be included by
+// several of them.
+#[derive(Clone)]
+enum Watched {
+    Texture(String),
+    Model(String),
+    Shader(Weak<AssetSlot<Shader>>),
+}
+
+impl Watched {
+    fn is(&self, other: &Watched) -> bool {
+        match (self, other) {
+            (Watched::Texture(a), Watched::Texture(b)) | (Watched::Model(a), Watched::Model(b)) => a == b,
+            (Watched::Shader(a), Watched::Shader(b)) => a.ptr_eq(b),
+            _ => false,
+        }
+    }
+}
+
+// A parsed model that is only uploaded once all of its textures are done, so
+// it never has to be rebuilt when they arrive.
+struct WaitingModel {
+    slot: Weak<AssetSlot<Model>>,
+    mesh: MeshFile,
+    textures: Vec<Handle<Texture>>,
+}
+
+// Loads textures, models and shaders by path, handing out shared handles so
+// an asset used in several places is only loaded and uploaded once.
+//
+// Textures and models load in the background: their handles point at a
+// placeholder until `update` uploads the result on the main thread.
+//
+// With hot reloading on, the default in debug builds, assets are loaded
+// again in place when their files change. If that fails the previous version
+// is kept and the error logged.
+pub struct AssetServer {
+    textures: AssetStore<Texture>,
+    models: AssetStore<Model>,
+    shaders: AssetStore<Shader>,
+    placeholder_texture: Arc<Texture>,
+    placeholder_model: Arc<Model>,
+    loader: Loader,
+    sender: mpsc::Sender<Completed>,
+    receiver: mpsc::Receiver<Completed>,
+    completed: VecDeque<Completed>,
+    waiting_models: Vec<WaitingModel>,
+    watcher: Option<FileWatcher>,
+    watched: HashMap<PathBuf, Vec<Watched>>,
+    // How long `update` may spend uploading per frame. At least one upload
+    // always happens so loading can't stall completely.
+    pub upload_budget: Duration,
+}
+
+impl AssetServer {
+    pub fn new(gpu: &GPUHandle) -> anyhow::Result<Self> {
+        let (sender, receiver) = mpsc::channel();
+        let mut server = AssetServer {
+            textures: AssetStore::new(),
+            models: AssetStore::new(),
+            shaders: AssetStore::new(),
+            placeholder_texture: Arc::new(placeholder_texture(gpu)?),
+            placeholder_model: Arc::new(Model::empty("placeholder_model")),
+            loader: Loader::new(),
+            sender,
+            receiver,
+            completed: VecDeque::new(),
+            waiting_models: Vec::new(),
+            watcher: None,
+            watched: HashMap::new(),
+            upload_budget: Duration::from_millis(4),
+        };
+        server.set_hot_reload(cfg!(debug_assertions));
+        Ok(server)
+    }
+
+    // Assets loaded before hot reloading is turned on aren't watched.
+    pub fn set_hot_reload(&mut self, enabled: bool) {
+        if !enabled {
+            self.watcher = None;
+            self.watched.clear();
+            return;
+        }
+        if self.watcher.is_none() {
+            match FileWatcher::new() {
+                Ok(watcher) => self.watcher = Some(watcher),
+                Err(e) => log::warn!("hot reloading is unavailable: {}", e),
+            }
+        }
+    }
+
+    // Assets read from a pack have no file to watch.
+    fn watch_asset(&mut self, path: &str, asset: Watched) {
+        if self.watcher.is_some() {
+            if let Some(path) = vfs().file_path(path) {
+                self.watch(&path, asset);
+            }
+        }
+    }
+
+    fn watch(&mut self, path: &Path, asset: Watched) {
+        if let Some(watcher) = &mut self.watcher {
+            match watcher.watch(path) {
+                Ok(path) => {
+                    let assets = self.watched.entry(path).or_default();
+                    if !assets.iter().any(|watched| watched.is(&asset)) {
+                        assets.push(asset);
+                    }
+                }
+                Err(e) => log::debug!("not watching {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    pub fn load_texture(&mut self, path: &str) -> Handle<Texture> {
+        if let Some(handle) = self.textures.get(path) {
+            return handle;
+        }
+        self.spawn_decode(path, decode_texture);
+        self.watch_asset(&source_name(path, COOKED_TEXTURE), Watched::Texture(path.to_string()));
+        self.textures.insert(path, self.placeholder_texture.clone(), LoadState::Loading)
+    }
+
+    pub fn add_texture(&mut self, name: &str, texture: Texture) -> Handle<Texture> {
+        self.textures.add(name, texture)
+    }
+
+    pub fn load_model(&mut self, path: &str) -> Handle<Model> {
+        if let Some(handle) = self.models.get(path) {
+            return handle;
+        }
+        self.spawn_decode(path, decode_model);
+        self.watch_asset(&source_name(path, COOKED_MESH), Watched::Model(path.to_string()));
+        self.models.insert(path, self.placeholder_model.clone(), LoadState::Loading)
+    }
+
+    pub fn add_model(&mut self, name: &str, model: Model) -> Handle<Model> {
+        self.models.add(name, model)
+    }
+
+    // Shaders are small and have to be compiled on the main thread anyway,
+    // so unlike other assets they load synchronously. They're preprocessed,
+    // see `ShaderSource`, and reloaded when any file they include changes.
+    pub fn load_shader(&mut self, path: &str, gpu: &GPUHandle) -> anyhow::Result<Handle<Shader>> {
+        self.load_shader_with(path, &[], gpu)
+    }
+
+    // Loads `path` with `defines` defined, cached separately for every set
+    // of defines.
+    pub fn load_shader_with(&mut self, path: &str, defines: &[(&str, &str)], gpu: &GPUHandle) -> anyhow::Result<Handle<Shader>> {
+        let mut key = path.to_string();
+        for (name, value) in defines {
+            key.push_str(&format!(" {}={}", name, value));
+        }
+        if let Some(handle) = self.shaders.get(&key) {
+            return Ok(handle);
+        }
+        let shader = Shader::load(gpu, path, defines)?;
+        let files = shader.files().to_vec();
+        let handle = self.shaders.insert(&key, Arc::new(shader), LoadState::Loaded);
+        self.watch_shader_files(handle.slot(), &files);
+        Ok(handle)
+    }
+
+    // Adds a shader from memory. Its includes are loaded relative to `name`.
+    pub fn add_shader(&mut self, name: &str, source: &str, gpu: &GPUHandle) -> anyhow::Result<Handle<Shader>> {
+        let source = ShaderSource::preprocess(name, source, &[], &mut |include| load_string(include))?;
+        let shader = Shader::from_source(gpu, &source, name)?;
+        Ok(self.shaders.add(name, shader))
+    }
+
+    fn watch_shader_files(&mut self, slot: &Arc<AssetSlot<Shader>>, files: &[String]) {
+        for file in files {
+            self.watch_asset(file, Watched::Shader(Arc::downgrade(slot)));
+        }
+    }
+
+    fn spawn_decode(&self, path: &str, decode: impl FnOnce(&str) -> anyhow::Result<Decoded> + Send + 'static) {
+        self.loader.spawn(decode_job(path, decode, self.sender.clone()));
+    }
+
+    // Uploads finished loads to the GPU, spending at most `upload_budget`.
+    pub fn update(&mut self, gpu: &GPUHandle, bindgroups: &BindGroups) {
+        self.reload_changed(gpu);
+        self.completed.extend(self.receiver.try_iter());
+
+        let start = Instant::now();
+        while let Some(completed) = self.completed.pop_front() {
+            self.upload(completed, gpu, bindgroups);
+            if start.elapsed() >= self.upload_budget {
+                break;
+            }
+        }
+
+        // Models whose textures have all finished, successfully or not.
+        let waiting = std::mem::take(&mut self.waiting_models);
+        for model in waiting {
+            if model.textures.iter().any(|texture| texture.state() == LoadState::Loading) {
+                self.waiting_models.push(model);
+                continue;
+            }
+            if let Some(slot) = model.slot.upgrade() {
+                let textures = model.textures.into_iter().map(|mut texture| {
+                    texture.refresh();
+                    texture
+                });
+                match Model::from_mesh_file(&slot.path, model.mesh, textures.collect(), gpu, bindgroups) {
+                    Ok(uploaded) => slot.finish(uploaded),
+                    Err(e) => report_failure(&slot, &e.into()),
+                }
+            }
+        }
+    }
+
+    fn reload_changed(&mut self, gpu: &GPUHandle) {
+        let Some(watcher) = &mut self.watcher else { return };
+        for path in watcher.changed() {
+            let assets = self.watched.get(&path).cloned().unwrap_or_default();
+            for asset in assets {
+                match asset {
+                    Watched::Texture(key) => {
+                        log::info!("reloading {}", key);
+                        self.spawn_decode(&key, decode_texture);
+                    }
+                    Watched::Model(key) => {
+                        log::info!("reloading {}", key);
+                        self.spawn_decode(&key, decode_model);
+                    }
+                    Watched::Shader(slot) => {
+                        let Some(slot) = slot.upgrade() else { continue };
+                        log::info!("reloading {}", slot.path);
+                        match slot.current().reload(gpu) {
+                            Ok(shader) => {
+                                // The shader may include different files now.
+                                let files = shader.files().to_vec();
+                                slot.finish(shader);
+                                self.watch_shader_files(&slot, &files);
+                            }
+                            Err(e) => report_failure(&slot, &e),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn upload(&mut self, completed: Completed, gpu: &GPUHandle, bindgroups: &BindGroups) {
+        match completed.result {
+            Ok(Decoded::Texture(bytes, image)) => {
+                let texture = Texture::from_decoded(&gpu.device, &gpu.queue, bytes, &image, &completed.path);
+                self.finish_texture(&completed.path, texture, gpu, bindgroups);
+            }
+            Ok(Decoded::CookedTexture(bytes)) => {
+                let texture = Texture::from_cooked(&gpu.device, &gpu.queue, bytes, &completed.path);
+                self.finish_texture(&completed.path, texture, gpu, bindgroups);
+            }
+            Ok(Decoded::Model(mesh)) => {
+                let Some(slot) = self.models.slot(&completed.path) else { return };
+                let textures = mesh.materials.iter().map(|material| self.load_texture(&material.diffuse_texture)).collect();
+                self.waiting_models.push(WaitingModel {
+                    slot: Arc::downgrade(&slot),
+                    mesh,
+                    textures,
+                });
+            }
+            Err(e) => {
+                if let Some(slot) = self.textures.slot(&completed.path) {
+                    report_failure(&slot, &e);
+                }
+                if let Some(slot) = self.models.slot(&completed.path) {
+                    report_failure(&slot, &e);
+                }
+            }
+        }
+    }
+
+    fn finish_texture(&mut self, path: &str, texture: anyhow::Result<Texture>, gpu: &GPUHandle, bindgroups: &BindGroups) {
+        // Nothing to do if every handle was dropped while it loaded.
+        let Some(slot) = self.textures.slot(path) else { return };
+        let reloaded = slot.state() == LoadState::Loaded;
+        match texture {
+            Ok(texture) => {
+                slot.finish(texture);
+                if reloaded {
+                    self.rebuild_models_using(path, gpu, bindgroups);
+                }
+            }
+            Err(e) => report_failure(&slot, &e),
+        }
+    }
+
+    // Models bake their textures into bind groups, so they're rebuilt when
+    // one of those textures is reloaded.
+    fn rebuild_models_using(&mut self, texture: &str, gpu: &GPUHandle, bindgroups: &BindGroups) {
+        for slot in self.models.live() {
+            let model = slot.current();
+            if slot.state() != LoadState::Loaded || !model.materials.iter().any(|m| m.diffuse_texture.path() == texture) {
+                continue;
+            }
+            match model.rebuild(gpu, bindgroups) {
+                Ok(model) => slot.replace(Arc::new(model)),
+                Err(e) => log::error!("failed to rebuild {} after reloading {}: {:?}", slot.path, texture, e),
+            }
+        }
+    }
+
+    // Recreates every loaded asset on a new device after the old one was lost.
+    // Textures go first since models rebuild their bind groups from them.
+    pub fn restore(&mut self, gpu: &GPUHandle, bindgroups: &BindGroups) -> anyhow::Result<()> {
+        self.placeholder_texture = Arc::new(placeholder_texture(gpu)?);
+        for slot in self.textures.live() {
+            if slot.state() == LoadState::Loaded {
+                slot.replace(Arc::new(slot.current().reupload(&gpu.device, &gpu.queue)?));
+            } else {
+                slot.replace(self.placeholder_texture.clone());
+            }
+        }
+        for slot in self.shaders.live() {
+            slot.replace(Arc::new(slot.current().reupload(gpu)?));
+        }
+        for slot in self.models.live() {
+            if slot.state() == LoadState::Loaded {
+                slot.replace(Arc::new(slot.current().rebuild(gpu, bindgroups)?));
+            }
+        }
+        Ok(())
+    }
+}
+
+// A failed reload keeps the asset as it was, a failed first load leaves the
+// placeholder in place.
+fn report_failure<T>(slot: &AssetSlot<T>, error: &anyhow::Error) {
+    if slot.state() == LoadState::Loaded {
+        log::error!("failed to reload {}, keeping the previous version: {:?}", slot.path, error);
+    } else {
+        slot.fail(error);
+    }
+}
+
+// Magenta, so missing textures stand out.
+fn placeholder_texture(gpu: &GPUHandle) -> anyhow::Result<Texture> {
+    let image = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba([255, 0, 255, 255])));
+    let mut png = Vec::new();
+    image.write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png)?;
+    Texture::from_decoded(&gpu.device, &gpu.queue, png.into(), &image, "placeholder_texture")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_panicking_load_fails_its_handle() {
+        let mut store = AssetStore::new();
+        let handle = store.insert("models/broken.obj", Arc::new(()), LoadState::Loading);
+
+        let loader = Loader::new();
+        let (sender, receiver) = mpsc::channel();
+        loader.spawn(decode_job("models/broken.obj", |_| panic!("no normals"), sender));
+        let completed = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+
+        let error = completed.result.err().unwrap();
+        report_failure(&store.slot(&completed.path).unwrap(), &error);
+        match handle.state() {
+            LoadState::Failed(message) => assert!(message.contains("no normals"), "{}", message),
+            state => panic!("the handle is {:?}", state),
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use anyhow::{bail, Context};
+
+use super::embedded::{self, EmbeddedMount};
+use super::SharedBytes;
+use super::pack::PackReader;
+use super::roots::default_asset_roots;
+
+// Somewhere assets can be read from, by their `/` separated asset path.
+pub trait Mount: Send + Sync {
+    fn contains(&self, path: &str) -> bool;
+
+    fn read(&self, path: &str) -> anyhow::Result<Vec<u8>>;
+
+    // Like `read`, but mounts that can hand out the bytes without copying
+    // them, like memory mapped files, do so.
+    fn map(&self, path: &str) -> anyhow::Result<SharedBytes> {
+        Ok(Arc::new(self.read(path)?))
+    }
+
+    // The file on disk behind `path`, if there is one to watch for changes.
+    fn file_path(&self, _path: &str) -> Option<PathBuf> {
+        None
+    }
+
+    fn describe(&self) -> String;
+}
+
+pub struct DirMount {
+    root: PathBuf,
+}
+
+impl DirMount {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        DirMount { root: root.into() }
+    }
+
+    // The file `path` refers to under the root, with `.` and `..` resolved.
+    // Absolute paths and ones that climb out of the root are refused.
+    fn resolve(&self, path: &str) -> anyhow::Result<PathBuf> {
+        if path.starts_with('/') {
+            bail!("asset path {} is absolute", path);
+        }
+        let mut parts: Vec<&str> = Vec::new();
+        for part in path.split('/') {
+            match part {
+                "" | "." => {}
+                ".." => {
+                    if parts.pop().is_none() {
+                        bail!("asset path {} leads outside of {}", path, self.root.display());
+                    }
+                }
+                // Parts that aren't a plain name, e.g. `C:` or `a\b` on Windows.
+                _ if !matches!(Path::new(part).components().collect::<Vec<_>>()[..], [Component::Normal(_)]) => {
+                    bail!("asset path {} has an invalid part {:?}", path, part);
+                }
+                _ => parts.push(part),
+            }
+        }
+        Ok(parts.iter().fold(self.root.clone(), |file, part| file.join(part)))
+    }
+}
+
+impl Mount for DirMount {
+    fn contains(&self, path: &str) -> bool {
+        self.resolve(path).is_ok_and(|path| path.is_file())
+    }
+
+    fn read(&self, path: &str) -> anyhow::Result<Vec<u8>> {
+        let path = self.resolve(path)?;
+        std::fs::read(&path).with_context(|| format!("failed to read {}", path.display()))
+    }
+
+    fn map(&self, path: &str) -> anyhow::Result<SharedBytes> {
+        let path = self.resolve(path)?;
+        let file = std::fs::File::open(&path).with_context(|| format!("failed to open {}", path.display()))?;
+        // Safety: the mapping is only read, and the cooker replaces files
+        // instead of writing into them, so mapped files don't change.
+        let map = unsafe { memmap2::Mmap::map(&file) }.with_context(|| format!("failed to map {}", path.display()))?;
+        Ok(Arc::new(map))
+    }
+
+    fn file_path(&self, path: &str) -> Option<PathBuf> {
+        self.resolve(path).ok()
+    }
+
+    fn describe(&self) -> String {
+        self.root.display().to_string()
+    }
+}
+
+impl Mount for PackReader {
+    fn contains(&self, path: &str) -> bool {
+        PackReader::contains(self, path)
+    }
+
+    fn read(&self, path: &str) -> anyhow::Result<Vec<u8>> {
+        PackReader::read(self, path)
+    }
+
+    fn describe(&self) -> String {
+        self.path().display().to_string()
+    }
+}
+
+// Layers mounts on top of each other. A file is read from the last mount
+// that has it, so mods and patches are mounted after the base game.
+#[derive(Clone, Default)]
+pub struct Vfs {
+    mounts: Vec<Arc<dyn Mount>>,
+}
+
+impl Vfs {
+    pub fn new() -> Self {
+        Vfs::default()
+    }
+
+    // Mounts every root, highest priority first as in `default_asset_roots`.
+    // Pack archives (`*.pak`) directly inside a root are mounted below its
+    // loose files, later ones by name overriding earlier ones. Embedded
+    // assets come last, if there are any.
+    pub fn from_roots(roots: &[PathBuf]) -> Self {
+        let mut vfs = Vfs::new();
+        if embedded::paths().next().is_some() {
+            vfs.mount(EmbeddedMount);
+        }
+        for root in roots.iter().rev() {
+            let mut packs = std::fs::read_dir(root)
+                .map(|entries| entries.filter_map(|entry| Some(entry.ok()?.path())).collect::<Vec<_>>())
+                .unwrap_or_default();
+            packs.retain(|path| path.is_file() && path.extension().is_some_and(|extension| extension == "pak"));
+            packs.sort();
+            for pack in packs {
+                if let Err(e) = vfs.mount_pack(&pack) {
+                    log::error!("skipping pack: {:?}", e);
+                }
+            }
+            vfs.mount_dir(root);
+        }
+        vfs
+    }
+
+    pub fn mount(&mut self, mount: impl Mount + 'static) {
+        self.mounts.push(Arc::new(mount));
+    }
+
+    pub fn mount_dir(&mut self, root: impl Into<PathBuf>) {
+        self.mount(DirMount::new(root));
+    }
+
+    pub fn mount_pack(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        self.mount(PackReader::open(path)?);
+        Ok(())
+    }
+
+    fn find(&self, path: &str) -> Option<&Arc<dyn Mount>> {
+        self.mounts.iter().rev().find(|mount| mount.contains(path))
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.find(path).is_some()
+    }
+
+    pub fn read(&self, path: &str) -> anyhow::Result<Vec<u8>> {
+        self.find_or_fail(path)?.read(path)
+    }
+
+    // The bytes of `path` without copying them where the mount allows it.
+    pub fn map(&self, path: &str) -> anyhow::Result<SharedBytes> {
+        self.find_or_fail(path)?.map(path)
+    }
+
+    fn find_or_fail(&self, path: &str) -> anyhow::Result<&Arc<dyn Mount>> {
+        match self.find(path) {
+            Some(mount) => Ok(mount),
+            None if self.mounts.is_empty() => bail!("asset {} not found, nothing is mounted", path),
+            None => bail!("asset {} not found in any of: {}", path, self.describe().join(", ")),
+        }
+    }
+
+    // The file on disk `read` would use for `path`, if it isn't in a pack.
+    pub fn file_path(&self, path: &str) -> Option<PathBuf> {
+        self.find(path)?.file_path(path)
+    }
+
+    // Every mount, highest priority first.
+    pub fn describe(&self) -> Vec<String> {
+        self.mounts.iter().rev().map(|mount| mount.describe()).collect()
+    }
+}
+
+static VFS: RwLock<Option<Arc<Vfs>>> = RwLock::new(None);
+
+// The filesystem assets are loaded from, mounting the default asset roots
+// if nothing was set up.
+pub fn vfs() -> Arc<Vfs> {
+    if let Some(vfs) = VFS.read().unwrap().as_ref() {
+        return vfs.clone();
+    }
+    VFS.write().unwrap().get_or_insert_with(|| Arc::new(Vfs::from_roots(&default_asset_roots(&[])))).clone()
+}
+
+pub fn set_vfs(vfs: Vfs) {
+    *VFS.write().unwrap() = Some(Arc::new(vfs));
+}
+
+// Adds a mount on top of the current ones, e.g. a mod loaded at runtime.
+// Assets that are already loaded aren't affected.
+pub fn mount(mount: impl Mount + 'static) {
+    let mut vfs = (*self::vfs()).clone();
+    vfs.mount(mount);
+    set_vfs(vfs);
+}
@@ -0,0 +1,75 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use notify::{RecursiveMode, Watcher};
+
+// Editors often write a file in several steps, so a change is only reported
+// once the file has been quiet for this long.
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+// Reports changes to individual files. Their directories are watched rather
+// than the files themselves, since saving by rename replaces the file.
+pub(super) struct FileWatcher {
+    watcher: notify::RecommendedWatcher,
+    events: mpsc::Receiver<PathBuf>,
+    directories: HashSet<PathBuf>,
+    files: HashSet<PathBuf>,
+    pending: HashMap<PathBuf, Instant>,
+}
+
+impl FileWatcher {
+    pub(super) fn new() -> notify::Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if let Ok(event) = event {
+                if event.kind.is_modify() || event.kind.is_create() {
+                    for path in event.paths {
+                        sender.send(path).ok();
+                    }
+                }
+            }
+        })?;
+
+        Ok(FileWatcher {
+            watcher,
+            events,
+            directories: HashSet::new(),
+            files: HashSet::new(),
+            pending: HashMap::new(),
+        })
+    }
+
+    // Returns the canonical path changes to `file` will be reported under.
+    pub(super) fn watch(&mut self, file: &Path) -> notify::Result<PathBuf> {
+        let file = file.canonicalize()?;
+        if let Some(directory) = file.parent() {
+            if !self.directories.contains(directory) {
+                self.watcher.watch(directory, RecursiveMode::NonRecursive)?;
+                self.directories.insert(directory.to_path_buf());
+            }
+        }
+        self.files.insert(file.clone());
+        Ok(file)
+    }
+
+    // Watched files that changed and have settled since the last call.
+    pub(super) fn changed(&mut self) -> Vec<PathBuf> {
+        let now = Instant::now();
+        for path in self.events.try_iter() {
+            if self.files.contains(&path) {
+                self.pending.insert(path, now);
+            }
+        }
+
+        let settled = self.pending.iter()
+            .filter(|(_, changed)| now.duration_since(**changed) >= DEBOUNCE)
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        for path in &settled {
+            self.pending.remove(path);
+        }
+        settled
+    }
+}
@@ -0,0 +1,115 @@
+use std::time::{Duration, Instant};
+
+// Frames slower than this only advance the simulation by this much, so one
+// long stall (a breakpoint, dragging the window) can't queue up hundreds of
+// fixed updates.
+const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+// Frame clock for the game loop. Simulation runs in fixed steps drained from
+// an accumulator, while rendering happens once per frame and uses `alpha` to
+// interpolate between the last two simulation states.
+pub struct Time {
+    delta: Duration,
+    elapsed: Duration,
+    frame_count: u64,
+    fixed_timestep: Duration,
+    accumulator: Duration,
+    alpha: f32,
+    last: Instant,
+}
+
+impl Time {
+    pub fn new(fixed_timestep: Duration) -> Self {
+        Time {
+            delta: Duration::ZERO,
+            elapsed: Duration::ZERO,
+            frame_count: 0,
+            fixed_timestep,
+            accumulator: Duration::ZERO,
+            alpha: 0.0,
+            last: Instant::now(),
+        }
+    }
+
+    // Time since the previous frame.
+    pub fn delta(&self) -> Duration {
+        self.delta
+    }
+
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta.as_secs_f32()
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn fixed_timestep(&self) -> Duration {
+        self.fixed_timestep
+    }
+
+    pub fn fixed_seconds(&self) -> f32 {
+        self.fixed_timestep.as_secs_f32()
+    }
+
+    // How far between the previous and the current fixed update this frame
+    // lies, from 0 to 1.
+    pub fn alpha(&self) -> f32 {
+        self.alpha
+    }
+
+    // Advances by the wall clock time since the last call and returns how
+    // many fixed updates are due.
+    pub fn tick(&mut self) -> u32 {
+        let now = Instant::now();
+        let delta = now - self.last;
+        self.last = now;
+        self.advance(delta)
+    }
+
+    // Advances by an explicit amount, e.g. a constant step while recording.
+    pub fn advance(&mut self, delta: Duration) -> u32 {
+        self.delta = delta;
+        self.elapsed += delta;
+        self.frame_count += 1;
+        self.accumulator += delta.min(MAX_FRAME_TIME);
+
+        let mut steps = 0;
+        while self.accumulator >= self.fixed_timestep {
+            self.accumulator -= self.fixed_timestep;
+            steps += 1;
+        }
+        self.alpha = self.accumulator.as_secs_f32() / self.fixed_timestep.as_secs_f32();
+        steps
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameRate {
+    Capped(u32),
+    Uncapped,
+}
+
+impl FrameRate {
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        if value == "uncapped" {
+            return Ok(FrameRate::Uncapped);
+        }
+        match value.parse() {
+            Ok(0) => anyhow::bail!("frame rate must be greater than zero, use \"uncapped\" to disable the limit"),
+            Ok(fps) => Ok(FrameRate::Capped(fps)),
+            Err(_) => anyhow::bail!("invalid frame rate {:?}, expected a number or \"uncapped\"", value),
+        }
+    }
+
+    pub fn frame_duration(&self) -> Option<Duration> {
+        match self {
+            FrameRate::Capped(fps) => Some(Duration::from_secs_f64(1.0 / *fps as f64)),
+            FrameRate::Uncapped => None,
+        }
+    }
+}
@@ -1,7 +1,58 @@
-use rmagic::run;
+use cgmath::Point3;
+use rmagic::{
+    camera::Camera,
+    cameracontroller::CameraController,
+    engine::{renderkit::RenderKit, resource, time::Time},
+    App, Engine, EngineConfig,
+};
+use winit::{dpi::PhysicalSize, event::WindowEvent, window::Window};
 
-use pollster;
+mod pentagon;
 
-fn main() {
-    pollster::block_on(run());
+struct Demo {
+    camera: Camera,
+    previous_eye: Point3<f32>,
+    camera_controller: CameraController,
+}
+
+impl App for Demo {
+    fn init(_window: &Window, renderkit: &mut RenderKit) -> anyhow::Result<Self> {
+        let diffuse_texture = renderkit.create_texture(&resource::load_binary("jerm.png")?, "jerm.png")?;
+        let model = renderkit.create_model("pentagon", pentagon::VERTICES, pentagon::INDICES, diffuse_texture)?;
+        renderkit.insert_renderable(Box::new(model));
+
+        let camera = Camera::new(&renderkit.gpu.config);
+        let previous_eye = camera.eye;
+
+        Ok(Demo {
+            camera,
+            previous_eye,
+            camera_controller: CameraController::new(12.0),
+        })
+    }
+
+    fn input(&mut self, event: &WindowEvent) -> bool {
+        self.camera_controller.process_events(event)
+    }
+
+    fn fixed_update(&mut self, _renderkit: &mut RenderKit, time: &Time) {
+        self.previous_eye = self.camera.eye;
+        self.camera_controller.update_camera(&mut self.camera, time.fixed_seconds());
+    }
+
+    fn update(&mut self, renderkit: &mut RenderKit, time: &Time) {
+        // Render the camera part way between the last two fixed updates so
+        // movement stays smooth when the frame rate and tick rate differ.
+        let mut camera = self.camera.clone();
+        camera.eye = self.previous_eye + (self.camera.eye - self.previous_eye) * time.alpha();
+        renderkit.update_camera(&camera);
+    }
+
+    fn resize(&mut self, _renderkit: &mut RenderKit, size: PhysicalSize<u32>) {
+        self.camera.resize(size.width, size.height);
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    Engine::run::<Demo>(EngineConfig::from_args()?)
 }